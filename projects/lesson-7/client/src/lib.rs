@@ -0,0 +1,316 @@
+//! Builds, signs and submits kitties extrinsics for the substrate-kitties runtime.
+//!
+//! Calls and signed extensions are the runtime's own types, so a change to `SignedExtra`
+//! breaks the build of this crate rather than the extrinsics it sends.
+
+use std::cell::Cell;
+use std::fmt;
+use codec::Encode;
+use primitives::{blake2_256, ed25519, sr25519, Pair};
+use sr_primitives::generic::Era;
+use sr_primitives::traits::SignedExtension;
+use substrate_kitties_runtime::VERSION;
+
+pub use substrate_kitties_runtime::{
+	AccountId, Balance, Call, Hash, Index, Signature, SignedExtra, UncheckedExtrinsic,
+};
+
+mod transport;
+
+pub use transport::{account_nonce_key, JsonRpcTransport, RpcError, Transport};
+
+/// Data each signed extension adds to the signed payload without including it in the extrinsic.
+type AdditionalSigned = <SignedExtra as SignedExtension>::AdditionalSigned;
+
+/// Typed constructors for the kitties calls of the runtime.
+pub mod calls {
+	use substrate_kitties_runtime::{kitties, AccountId, Balance, Call};
+
+	/// Create a kitty with random DNA.
+	pub fn create() -> Call {
+		Call::Kitties(kitties::Call::create())
+	}
+
+	/// Breed two kitties held by the sender.
+	pub fn breed(kitty_id_1: u32, kitty_id_2: u32) -> Call {
+		Call::Kitties(kitties::Call::breed(kitty_id_1, kitty_id_2))
+	}
+
+	/// Give a kitty to another account.
+	pub fn transfer(to: AccountId, kitty_id: u32) -> Call {
+		Call::Kitties(kitties::Call::transfer(to, kitty_id))
+	}
+
+	/// List a kitty for sale, or take it off sale with None.
+	pub fn ask(kitty_id: u32, price: Option<Balance>) -> Call {
+		Call::Kitties(kitties::Call::ask(kitty_id, price))
+	}
+
+	/// Buy a listed kitty. `nonce` is the listing nonce the price was read at.
+	pub fn buy(kitty_id: u32, nonce: u32, max_price: Balance) -> Call {
+		Call::Kitties(kitties::Call::buy(kitty_id, nonce, max_price))
+	}
+}
+
+/// A key that signs extrinsics. The runtime's `AnySignature` accepts both sr25519 and
+/// ed25519 signatures for the same 32 byte account id.
+pub trait Signer {
+	/// The account the signatures are for.
+	fn account_id(&self) -> AccountId;
+	/// Sign an extrinsic payload.
+	fn sign(&self, payload: &[u8]) -> Signature;
+}
+
+impl Signer for sr25519::Pair {
+	fn account_id(&self) -> AccountId {
+		self.public()
+	}
+
+	fn sign(&self, payload: &[u8]) -> Signature {
+		Pair::sign(self, payload).into()
+	}
+}
+
+impl Signer for ed25519::Pair {
+	fn account_id(&self) -> AccountId {
+		sr25519::Public::from_raw(self.public().0)
+	}
+
+	fn sign(&self, payload: &[u8]) -> Signature {
+		Pair::sign(self, payload).into()
+	}
+}
+
+/// The chain a signature is valid for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainInfo {
+	/// Hash of block 0
+	pub genesis_hash: Hash,
+	/// `spec_version` of the runtime
+	pub spec_version: u32,
+}
+
+/// Sign `call` as an immortal extrinsic of `signer` with the given nonce and tip.
+pub fn sign<S: Signer>(signer: &S, call: Call, nonce: Index, tip: Balance, chain: &ChainInfo) -> UncheckedExtrinsic {
+	let extra: SignedExtra = (
+		system::CheckVersion::new(),
+		system::CheckGenesis::new(),
+		system::CheckEra::from(Era::Immortal),
+		system::CheckNonce::from(nonce),
+		system::CheckWeight::new(),
+		balances::TakeFees::from(tip),
+	);
+	// An immortal era is checked against the genesis hash
+	let additional: AdditionalSigned = (chain.spec_version, chain.genesis_hash, chain.genesis_hash, (), (), ());
+	let signature = signer.sign(&signing_payload(&call, &extra, &additional));
+	UncheckedExtrinsic::new_signed(call, signer.account_id().into(), signature, extra)
+}
+
+/// What is signed for an extrinsic. Payloads longer than 256 bytes are signed by hash, as
+/// the runtime verifies them.
+fn signing_payload(call: &Call, extra: &SignedExtra, additional: &AdditionalSigned) -> Vec<u8> {
+	let payload = (call, extra, additional).encode();
+	if payload.len() > 256 {
+		blake2_256(&payload).to_vec()
+	} else {
+		payload
+	}
+}
+
+/// Error of a `KittiesClient`.
+#[derive(Debug)]
+pub enum Error<E> {
+	/// The transport failed
+	Transport(E),
+	/// The chain runs a runtime this crate was not built against, so its extrinsics would
+	/// be rejected or misread
+	SpecVersionMismatch {
+		/// `spec_version` of the runtime this crate was built against
+		local: u32,
+		/// `spec_version` of the chain
+		chain: u32,
+	},
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Transport(e) => write!(f, "{}", e),
+			Error::SpecVersionMismatch { local, chain } =>
+				write!(f, "Built for runtime spec version {}, but the chain runs {}", local, chain),
+		}
+	}
+}
+
+/// Signs kitties calls with one key and submits them through a `Transport`.
+pub struct KittiesClient<T, S> {
+	transport: T,
+	signer: S,
+	chain: ChainInfo,
+	/// The nonce after the last extrinsic submitted, which the chain state does not know
+	/// about until it is included
+	next_nonce: Cell<Index>,
+}
+
+impl<T: Transport, S: Signer> KittiesClient<T, S> {
+	/// Connect through `transport`. Fails if the chain runs a different runtime version.
+	pub fn new(transport: T, signer: S) -> Result<Self, Error<T::Error>> {
+		let spec_version = transport.spec_version().map_err(Error::Transport)?;
+		if spec_version != VERSION.spec_version {
+			return Err(Error::SpecVersionMismatch { local: VERSION.spec_version, chain: spec_version });
+		}
+		let genesis_hash = transport.genesis_hash().map_err(Error::Transport)?;
+
+		Ok(KittiesClient {
+			transport,
+			signer,
+			chain: ChainInfo { genesis_hash, spec_version },
+			next_nonce: Cell::new(0),
+		})
+	}
+
+	/// The account that signs.
+	pub fn account_id(&self) -> AccountId {
+		self.signer.account_id()
+	}
+
+	/// Create a kitty. Returns the extrinsic hash.
+	pub fn create(&self) -> Result<Hash, Error<T::Error>> {
+		self.submit(calls::create())
+	}
+
+	/// Breed two kitties. Returns the extrinsic hash.
+	pub fn breed(&self, kitty_id_1: u32, kitty_id_2: u32) -> Result<Hash, Error<T::Error>> {
+		self.submit(calls::breed(kitty_id_1, kitty_id_2))
+	}
+
+	/// Give a kitty to another account. Returns the extrinsic hash.
+	pub fn transfer(&self, to: AccountId, kitty_id: u32) -> Result<Hash, Error<T::Error>> {
+		self.submit(calls::transfer(to, kitty_id))
+	}
+
+	/// List a kitty for sale, or take it off sale with None. Returns the extrinsic hash.
+	pub fn ask(&self, kitty_id: u32, price: Option<Balance>) -> Result<Hash, Error<T::Error>> {
+		self.submit(calls::ask(kitty_id, price))
+	}
+
+	/// Buy a listed kitty. Returns the extrinsic hash.
+	pub fn buy(&self, kitty_id: u32, nonce: u32, max_price: Balance) -> Result<Hash, Error<T::Error>> {
+		self.submit(calls::buy(kitty_id, nonce, max_price))
+	}
+
+	/// Sign any call with the next nonce of the signer and submit it. Returns the extrinsic hash.
+	pub fn submit(&self, call: Call) -> Result<Hash, Error<T::Error>> {
+		let chain_nonce = self.transport.account_nonce(&self.signer.account_id()).map_err(Error::Transport)?;
+		let nonce = chain_nonce.max(self.next_nonce.get());
+		let extrinsic = sign(&self.signer, call, nonce, 0, &self.chain);
+		let hash = self.transport.submit(extrinsic.encode()).map_err(Error::Transport)?;
+		self.next_nonce.set(nonce + 1);
+		Ok(hash)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::RefCell;
+	use codec::Decode;
+	use sr_primitives::traits::Verify;
+	use substrate_kitties_runtime::Address;
+
+	const GENESIS_HASH: [u8; 32] = [7; 32];
+
+	fn chain() -> ChainInfo {
+		ChainInfo { genesis_hash: GENESIS_HASH.into(), spec_version: VERSION.spec_version }
+	}
+
+	fn assert_signed_by<S: Signer>(signer: &S, extrinsic: &UncheckedExtrinsic) {
+		let (address, signature, extra) = extrinsic.signature.clone().expect("Extrinsic is signed");
+		assert!(address == Address::from(signer.account_id()));
+		let chain = chain();
+		let additional = (chain.spec_version, chain.genesis_hash, chain.genesis_hash, (), (), ());
+		let payload = signing_payload(&extrinsic.function, &extra, &additional);
+		assert!(signature.verify(&payload[..], &signer.account_id()));
+	}
+
+	#[test]
+	fn sr25519_and_ed25519_keys_sign() {
+		let sr = sr25519::Pair::from_string("//Alice", None).unwrap();
+		let extrinsic = sign(&sr, calls::create(), 0, 0, &chain());
+		assert_signed_by(&sr, &extrinsic);
+
+		let ed = ed25519::Pair::from_string("//Alice", None).unwrap();
+		let extrinsic = sign(&ed, calls::transfer(sr.account_id(), 3), 5, 0, &chain());
+		assert_signed_by(&ed, &extrinsic);
+	}
+
+	#[test]
+	fn extrinsics_round_trip() {
+		let pair = sr25519::Pair::from_string("//Bob", None).unwrap();
+		let extrinsic = sign(&pair, calls::buy(1, 2, 300), 4, 0, &chain());
+		let decoded = UncheckedExtrinsic::decode(&mut &extrinsic.encode()[..]).unwrap();
+		assert!(decoded.function == calls::buy(1, 2, 300));
+		assert_signed_by(&pair, &decoded);
+	}
+
+	/// Records submitted extrinsics, with a fixed chain state.
+	struct MockTransport {
+		spec_version: u32,
+		nonce: Index,
+		submitted: RefCell<Vec<UncheckedExtrinsic>>,
+	}
+
+	impl Transport for MockTransport {
+		type Error = ();
+
+		fn genesis_hash(&self) -> Result<Hash, ()> {
+			Ok(GENESIS_HASH.into())
+		}
+
+		fn spec_version(&self) -> Result<u32, ()> {
+			Ok(self.spec_version)
+		}
+
+		fn account_nonce(&self, _account: &AccountId) -> Result<Index, ()> {
+			Ok(self.nonce)
+		}
+
+		fn submit(&self, extrinsic: Vec<u8>) -> Result<Hash, ()> {
+			let hash = blake2_256(&extrinsic).into();
+			self.submitted.borrow_mut().push(UncheckedExtrinsic::decode(&mut &extrinsic[..]).map_err(|_| ())?);
+			Ok(hash)
+		}
+	}
+
+	#[test]
+	fn client_counts_nonces_of_pending_extrinsics() {
+		let transport = MockTransport { spec_version: VERSION.spec_version, nonce: 5, submitted: RefCell::new(Vec::new()) };
+		let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+		let client = KittiesClient::new(transport, sr25519::Pair::from_string("//Alice", None).unwrap()).unwrap();
+
+		assert!(client.create().is_ok());
+		assert!(client.ask(0, Some(100)).is_ok());
+
+		let submitted = client.transport.submitted.borrow();
+		assert_eq!(submitted.len(), 2);
+		let nonces: Vec<_> = submitted.iter()
+			.map(|extrinsic| (extrinsic.signature.as_ref().unwrap().2).3.encode())
+			.collect();
+		assert_eq!(nonces, vec![
+			system::CheckNonce::<substrate_kitties_runtime::Runtime>::from(5).encode(),
+			system::CheckNonce::<substrate_kitties_runtime::Runtime>::from(6).encode(),
+		]);
+		assert!(submitted[1].function == calls::ask(0, Some(100)));
+		submitted.iter().for_each(|extrinsic| assert_signed_by(&pair, extrinsic));
+	}
+
+	#[test]
+	fn client_refuses_other_runtime_versions() {
+		let transport = MockTransport { spec_version: VERSION.spec_version + 1, nonce: 0, submitted: RefCell::new(Vec::new()) };
+		let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+		match KittiesClient::new(transport, pair) {
+			Err(Error::SpecVersionMismatch { local, chain }) => assert_eq!(chain, local + 1),
+			_ => panic!("Client accepted another runtime version"),
+		}
+	}
+}
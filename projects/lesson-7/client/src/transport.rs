@@ -0,0 +1,100 @@
+//! How the client reaches a node.
+
+use std::fmt;
+use codec::{Decode, Encode};
+use primitives::{blake2_256, Bytes};
+use serde_json::{json, Value};
+use substrate_kitties_runtime::{AccountId, Hash, Index};
+
+/// Submits extrinsics and reads the chain state that signing needs. Implement it over
+/// whatever RPC client a bot already uses, or in process for tests.
+pub trait Transport {
+	/// Error of the underlying connection
+	type Error;
+
+	/// Hash of block 0, which every signature commits to.
+	fn genesis_hash(&self) -> Result<Hash, Self::Error>;
+	/// `spec_version` of the runtime at the best block.
+	fn spec_version(&self) -> Result<u32, Self::Error>;
+	/// The nonce of `account` at the best block, which is the nonce of its next extrinsic.
+	fn account_nonce(&self, account: &AccountId) -> Result<Index, Self::Error>;
+	/// Submit an encoded extrinsic to the transaction pool. Returns its hash.
+	fn submit(&self, extrinsic: Vec<u8>) -> Result<Hash, Self::Error>;
+}
+
+/// Storage key of `System AccountNonce` for an account.
+pub fn account_nonce_key(account: &AccountId) -> Vec<u8> {
+	let mut key = b"System AccountNonce".to_vec();
+	account.encode_to(&mut key);
+	blake2_256(&key).to_vec()
+}
+
+/// Error of a `JsonRpcTransport`.
+#[derive(Debug)]
+pub enum RpcError<E> {
+	/// The request failed
+	Call(E),
+	/// The node answered with something other than the expected result
+	UnexpectedResponse(&'static str, Value),
+}
+
+impl<E: fmt::Display> fmt::Display for RpcError<E> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RpcError::Call(e) => write!(f, "RPC request failed: {}", e),
+			RpcError::UnexpectedResponse(method, response) => write!(f, "Unexpected response to {}: {}", method, response),
+		}
+	}
+}
+
+/// A `Transport` over the node's JSON-RPC API. `request` sends a method with its params and
+/// returns the `result` of the response, for example through an HTTP or WebSocket client.
+pub struct JsonRpcTransport<F> {
+	request: F,
+}
+
+impl<F, E> JsonRpcTransport<F> where
+	F: Fn(&str, Value) -> Result<Value, E>,
+{
+	pub fn new(request: F) -> Self {
+		JsonRpcTransport { request }
+	}
+
+	fn call<T: serde::de::DeserializeOwned>(&self, method: &'static str, params: Value) -> Result<T, RpcError<E>> {
+		let response = (self.request)(method, params).map_err(RpcError::Call)?;
+		serde_json::from_value(response.clone()).map_err(|_| RpcError::UnexpectedResponse(method, response))
+	}
+}
+
+impl<F, E> Transport for JsonRpcTransport<F> where
+	F: Fn(&str, Value) -> Result<Value, E>,
+{
+	type Error = RpcError<E>;
+
+	fn genesis_hash(&self) -> Result<Hash, Self::Error> {
+		self.call("chain_getBlockHash", json!([0]))
+	}
+
+	fn spec_version(&self) -> Result<u32, Self::Error> {
+		let version: Value = self.call("state_getRuntimeVersion", json!([]))?;
+		version.get("specVersion")
+			.and_then(Value::as_u64)
+			.map(|v| v as u32)
+			.ok_or_else(|| RpcError::UnexpectedResponse("state_getRuntimeVersion", version.clone()))
+	}
+
+	fn account_nonce(&self, account: &AccountId) -> Result<Index, Self::Error> {
+		let key = Bytes(account_nonce_key(account));
+		let value: Option<Bytes> = self.call("state_getStorage", json!([key]))?;
+		match value {
+			Some(value) => Index::decode(&mut &value.0[..])
+				.map_err(|_| RpcError::UnexpectedResponse("state_getStorage", json!([value]))),
+			// Accounts that never sent an extrinsic have no nonce stored
+			None => Ok(0),
+		}
+	}
+
+	fn submit(&self, extrinsic: Vec<u8>) -> Result<Hash, Self::Error> {
+		self.call("author_submitExtrinsic", json!([Bytes(extrinsic)]))
+	}
+}
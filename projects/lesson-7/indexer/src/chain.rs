@@ -0,0 +1,140 @@
+//! Reading blocks and kitties events from a node.
+
+use std::convert::TryFrom;
+use codec::{Decode, Encode};
+use primitives::{twox_128, Bytes};
+use primitives::crypto::Ss58AddressFormat;
+use serde_json::{json, Value};
+use substrate_kitties_runtime::{kitties, AccountId, Balance, BlockNumber, Event, Hash, Runtime, SS58_FORMAT};
+
+/// An event of the kitties module.
+pub type KittiesEvent = kitties::Event<Runtime>;
+
+/// A kitty as the runtime API returns it.
+pub type KittyDetails = kitties::KittyDetails<AccountId, Balance>;
+
+/// The SS58 address format with version `version`.
+pub fn ss58_format(version: u8) -> Ss58AddressFormat {
+	Ss58AddressFormat::try_from(version).unwrap_or(Ss58AddressFormat::Custom(version))
+}
+
+/// The chain as the indexer sees it.
+pub trait Chain {
+	/// Number of the last finalized block.
+	fn finalized_number(&self) -> Result<BlockNumber, String>;
+	/// Number of the best block.
+	fn best_number(&self) -> Result<BlockNumber, String>;
+	/// Hash of the block at `number` on the best chain, None above the best block.
+	fn block_hash(&self, number: BlockNumber) -> Result<Option<Hash>, String>;
+	/// Hash of the parent of a block.
+	fn parent_hash(&self, hash: Hash) -> Result<Hash, String>;
+	/// The kitties events of a block, in the order they were deposited.
+	fn kitties_events(&self, hash: Hash) -> Result<Vec<KittiesEvent>, String>;
+	/// Every kitty in the state of a block, with its index.
+	fn kitties_at(&self, hash: Hash) -> Result<Vec<(u32, KittyDetails)>, String>;
+}
+
+/// A node reached over HTTP JSON-RPC.
+pub struct HttpRpc {
+	url: String,
+	client: reqwest::Client,
+}
+
+impl HttpRpc {
+	pub fn new(url: &str) -> Self {
+		HttpRpc {
+			url: url.into(),
+			client: reqwest::Client::new(),
+		}
+	}
+
+	/// Send a JSON-RPC request and return its result.
+	pub fn request(&self, method: &str, params: Value) -> Result<Value, String> {
+		let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+		let mut response: Value = self.client.post(&self.url)
+			.json(&body)
+			.send()
+			.and_then(|mut response| response.json())
+			.map_err(|e| format!("Request {} to {} failed: {}", method, self.url, e))?;
+		if let Some(error) = response.get("error") {
+			return Err(format!("Request {} failed: {}", method, error));
+		}
+		Ok(response["result"].take())
+	}
+
+	/// The SS58 format of the chain's addresses, from the `ss58Format` property of its chain
+	/// spec, or the runtime's format if it has none.
+	pub fn address_format(&self) -> Result<Ss58AddressFormat, String> {
+		let properties = self.request("system_properties", json!([]))?;
+		let version = properties["ss58Format"].as_u64().map(|v| v as u8).unwrap_or(SS58_FORMAT);
+		Ok(ss58_format(version))
+	}
+
+	/// Call a function of the runtime API at a block and decode its result.
+	fn runtime_call<R: Decode>(&self, function: &str, args: impl Encode, hash: Hash) -> Result<R, String> {
+		let result = self.request("state_call", json!([function, Bytes(args.encode()), hash]))?;
+		let result: Bytes = serde_json::from_value(result)
+			.map_err(|e| format!("Invalid result of {} at {}: {}", function, hash, e))?;
+		R::decode(&mut &result.0[..])
+			.map_err(|e| format!("Result of {} at {} does not decode: {:?}", function, hash, e))
+	}
+
+	fn header_number(&self, params: Value) -> Result<BlockNumber, String> {
+		let header = self.request("chain_getHeader", params)?;
+		header["number"].as_str()
+			.and_then(|number| BlockNumber::from_str_radix(number.trim_start_matches("0x"), 16).ok())
+			.ok_or_else(|| format!("Header without a block number: {}", header))
+	}
+}
+
+impl Chain for HttpRpc {
+	fn finalized_number(&self) -> Result<BlockNumber, String> {
+		let hash = self.request("chain_getFinalizedHead", json!([]))?;
+		self.header_number(json!([hash]))
+	}
+
+	fn best_number(&self) -> Result<BlockNumber, String> {
+		self.header_number(json!([]))
+	}
+
+	fn block_hash(&self, number: BlockNumber) -> Result<Option<Hash>, String> {
+		let hash = self.request("chain_getBlockHash", json!([number]))?;
+		serde_json::from_value(hash).map_err(|e| format!("Invalid block hash: {}", e))
+	}
+
+	fn parent_hash(&self, hash: Hash) -> Result<Hash, String> {
+		let mut header = self.request("chain_getHeader", json!([hash]))?;
+		if header.is_null() {
+			return Err(format!("Unknown block {}", hash));
+		}
+		serde_json::from_value(header["parentHash"].take())
+			.map_err(|e| format!("Invalid parent hash of block {}: {}", hash, e))
+	}
+
+	fn kitties_events(&self, hash: Hash) -> Result<Vec<KittiesEvent>, String> {
+		let key = Bytes(twox_128(b"System Events").to_vec());
+		let events = self.request("state_getStorage", json!([key, hash]))?;
+		let events: Option<Bytes> = serde_json::from_value(events)
+			.map_err(|e| format!("Invalid events of block {}: {}", hash, e))?;
+		let records = match events {
+			Some(events) => Vec::<system::EventRecord<Event, Hash>>::decode(&mut &events.0[..])
+				.map_err(|e| format!("Events of block {} do not decode: {:?}", hash, e))?,
+			None => Vec::new(),
+		};
+		Ok(records.into_iter().filter_map(|record| match record.event {
+			Event::kitties(event) => Some(event),
+			_ => None,
+		}).collect())
+	}
+
+	fn kitties_at(&self, hash: Hash) -> Result<Vec<(u32, KittyDetails)>, String> {
+		let count: u32 = self.runtime_call("KittiesApi_kitties_count", (), hash)?;
+		let mut kitties = Vec::new();
+		for kitty_id in 0..count {
+			if let Some(kitty) = self.runtime_call::<Option<KittyDetails>>("KittiesApi_kitty", kitty_id, hash)? {
+				kitties.push((kitty_id, kitty));
+			}
+		}
+		Ok(kitties)
+	}
+}
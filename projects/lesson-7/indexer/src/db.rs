@@ -0,0 +1,229 @@
+//! The SQLite database of indexed kitties.
+//!
+//! `blocks` and `events` record what was indexed. `kitties`, `listings` and `trades` are
+//! derived from `events`, so a rollback deletes the events of the dropped blocks and replays
+//! the rest.
+
+use std::path::Path;
+use codec::{Decode, Encode};
+use primitives::crypto::{Ss58AddressFormat, Ss58Codec};
+use rusqlite::{params, Connection, OptionalExtension, Transaction, NO_PARAMS};
+use substrate_kitties_runtime::{kitties::RawEvent, AccountId, BlockNumber, Hash};
+use crate::chain::KittiesEvent;
+
+const SCHEMA: &str = "
+	CREATE TABLE IF NOT EXISTS blocks (
+		number INTEGER PRIMARY KEY,
+		hash TEXT NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS events (
+		block_number INTEGER NOT NULL,
+		event_index INTEGER NOT NULL,
+		data BLOB NOT NULL,
+		PRIMARY KEY (block_number, event_index)
+	);
+	CREATE TABLE IF NOT EXISTS kitties (
+		id INTEGER PRIMARY KEY,
+		owner TEXT NOT NULL,
+		created_block INTEGER NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS listings (
+		kitty_id INTEGER PRIMARY KEY,
+		seller TEXT NOT NULL,
+		price TEXT NOT NULL,
+		listed_block INTEGER NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS trades (
+		block_number INTEGER NOT NULL,
+		event_index INTEGER NOT NULL,
+		kitty_id INTEGER NOT NULL,
+		seller TEXT NOT NULL,
+		buyer TEXT NOT NULL,
+		price TEXT NOT NULL,
+		PRIMARY KEY (block_number, event_index)
+	);
+";
+
+/// A trade of a kitty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trade {
+	pub block_number: BlockNumber,
+	pub kitty_id: u32,
+	pub seller: String,
+	pub buyer: String,
+	pub price: String,
+}
+
+/// Kitties, owners, listings and trades up to the last indexed block. Accounts are stored
+/// as SS58 addresses in the chain's format and balances as decimal strings.
+pub struct Database {
+	connection: Connection,
+	addresses: Ss58AddressFormat,
+}
+
+fn db_error(e: rusqlite::Error) -> String {
+	format!("Database error: {}", e)
+}
+
+impl Database {
+	/// Open or create the database at `path`, storing addresses in format `addresses`.
+	pub fn open(path: &Path, addresses: Ss58AddressFormat) -> Result<Self, String> {
+		Self::with_connection(Connection::open(path).map_err(db_error)?, addresses)
+	}
+
+	/// A database that only lives in memory.
+	pub fn in_memory(addresses: Ss58AddressFormat) -> Result<Self, String> {
+		Self::with_connection(Connection::open_in_memory().map_err(db_error)?, addresses)
+	}
+
+	fn with_connection(connection: Connection, addresses: Ss58AddressFormat) -> Result<Self, String> {
+		connection.execute_batch(SCHEMA).map_err(db_error)?;
+		Ok(Database { connection, addresses })
+	}
+
+	/// Number of the last indexed block, None if nothing is indexed yet.
+	pub fn last_block(&self) -> Result<Option<BlockNumber>, String> {
+		self.connection.query_row("SELECT MAX(number) FROM blocks", NO_PARAMS, |row| row.get(0))
+			.map_err(db_error)
+	}
+
+	/// Hash of the indexed block at `number`.
+	pub fn block_hash(&self, number: BlockNumber) -> Result<Option<Hash>, String> {
+		let hash: Option<String> = self.connection
+			.query_row("SELECT hash FROM blocks WHERE number = ?1", params![number], |row| row.get(0))
+			.optional()
+			.map_err(db_error)?;
+		hash.map(|hash| hash.trim_start_matches("0x").parse()
+				.map_err(|_| format!("Invalid hash {} stored for block {}", hash, number)))
+			.transpose()
+	}
+
+	/// Record a block and apply its kitties events.
+	pub fn insert_block(&mut self, number: BlockNumber, hash: Hash, events: &[KittiesEvent]) -> Result<(), String> {
+		let tx = self.connection.transaction().map_err(db_error)?;
+		tx.execute("INSERT INTO blocks (number, hash) VALUES (?1, ?2)", params![number, format!("{:?}", hash)])
+			.map_err(db_error)?;
+		for (index, event) in events.iter().enumerate() {
+			tx.execute(
+				"INSERT INTO events (block_number, event_index, data) VALUES (?1, ?2, ?3)",
+				params![number, index as u32, event.encode()],
+			).map_err(db_error)?;
+			apply_event(&tx, self.addresses, number, index as u32, event)?;
+		}
+		tx.commit().map_err(db_error)
+	}
+
+	/// Forget every block above `number` and rebuild the derived tables from the events
+	/// that are left.
+	pub fn rollback_to(&mut self, number: BlockNumber) -> Result<(), String> {
+		let tx = self.connection.transaction().map_err(db_error)?;
+		tx.execute("DELETE FROM blocks WHERE number > ?1", params![number]).map_err(db_error)?;
+		tx.execute("DELETE FROM events WHERE block_number > ?1", params![number]).map_err(db_error)?;
+		tx.execute_batch("DELETE FROM kitties; DELETE FROM listings; DELETE FROM trades;").map_err(db_error)?;
+
+		let events = {
+			let mut statement = tx
+				.prepare("SELECT block_number, event_index, data FROM events ORDER BY block_number, event_index")
+				.map_err(db_error)?;
+			let rows = statement
+				.query_map(NO_PARAMS, |row| Ok((row.get::<_, u32>(0)?, row.get::<_, u32>(1)?, row.get::<_, Vec<u8>>(2)?)))
+				.map_err(db_error)?;
+			rows.collect::<Result<Vec<_>, _>>().map_err(db_error)?
+		};
+		for (block_number, index, data) in events {
+			let event = KittiesEvent::decode(&mut &data[..])
+				.map_err(|e| format!("Stored event {}-{} does not decode: {:?}", block_number, index, e))?;
+			apply_event(&tx, self.addresses, block_number, index, &event)?;
+		}
+		tx.commit().map_err(db_error)
+	}
+
+	/// The owner of a kitty.
+	pub fn owner(&self, kitty_id: u32) -> Result<Option<String>, String> {
+		self.connection
+			.query_row("SELECT owner FROM kitties WHERE id = ?1", params![kitty_id], |row| row.get(0))
+			.optional()
+			.map_err(db_error)
+	}
+
+	/// The asking price of a kitty, None if it is not for sale.
+	pub fn listing_price(&self, kitty_id: u32) -> Result<Option<String>, String> {
+		self.connection
+			.query_row("SELECT price FROM listings WHERE kitty_id = ?1", params![kitty_id], |row| row.get(0))
+			.optional()
+			.map_err(db_error)
+	}
+
+	/// The number of kitties indexed.
+	pub fn kitties_count(&self) -> Result<u32, String> {
+		self.connection.query_row("SELECT COUNT(*) FROM kitties", NO_PARAMS, |row| row.get(0))
+			.map_err(db_error)
+	}
+
+	/// The trades of a kitty, oldest first.
+	pub fn trades(&self, kitty_id: u32) -> Result<Vec<Trade>, String> {
+		let mut statement = self.connection
+			.prepare("SELECT block_number, seller, buyer, price FROM trades WHERE kitty_id = ?1 ORDER BY block_number, event_index")
+			.map_err(db_error)?;
+		let rows = statement
+			.query_map(params![kitty_id], |row| Ok(Trade {
+				block_number: row.get(0)?,
+				kitty_id,
+				seller: row.get(1)?,
+				buyer: row.get(2)?,
+				price: row.get(3)?,
+			}))
+			.map_err(db_error)?;
+		rows.collect::<Result<Vec<_>, _>>().map_err(db_error)
+	}
+}
+
+/// Update the derived tables for one event. Besides the creation and trading events this
+/// follows every event that moves a kitty, so owners stay right.
+fn apply_event(
+	tx: &Transaction,
+	addresses: Ss58AddressFormat,
+	block_number: BlockNumber,
+	index: u32,
+	event: &KittiesEvent,
+) -> Result<(), String> {
+	let address = |account: &AccountId| account.to_ss58check_with_version(addresses);
+	match event {
+		RawEvent::Created(owner, kitty_id) => {
+			tx.execute(
+				"INSERT OR REPLACE INTO kitties (id, owner, created_block) VALUES (?1, ?2, ?3)",
+				params![*kitty_id, address(owner), block_number],
+			).map_err(db_error)?;
+		}
+		RawEvent::Transferred(_, to, kitty_id)
+		| RawEvent::ForceTransferred(_, to, kitty_id)
+		| RawEvent::CollateralSeized(_, to, kitty_id) => {
+			set_owner(tx, *kitty_id, &address(to))?;
+		}
+		RawEvent::Ask(seller, kitty_id, Some(price)) => {
+			tx.execute(
+				"INSERT OR REPLACE INTO listings (kitty_id, seller, price, listed_block) VALUES (?1, ?2, ?3, ?4)",
+				params![*kitty_id, address(seller), price.to_string(), block_number],
+			).map_err(db_error)?;
+		}
+		RawEvent::Ask(_, kitty_id, None) | RawEvent::ForceDelisted(_, kitty_id) => {
+			tx.execute("DELETE FROM listings WHERE kitty_id = ?1", params![*kitty_id]).map_err(db_error)?;
+		}
+		RawEvent::Sold(seller, buyer, kitty_id, price) => {
+			set_owner(tx, *kitty_id, &address(buyer))?;
+			tx.execute(
+				"INSERT INTO trades (block_number, event_index, kitty_id, seller, buyer, price) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+				params![block_number, index, *kitty_id, address(seller), address(buyer), price.to_string()],
+			).map_err(db_error)?;
+		}
+		_ => {}
+	}
+	Ok(())
+}
+
+/// Record a new owner. A kitty that changes hands is no longer listed by its old owner.
+fn set_owner(tx: &Transaction, kitty_id: u32, owner: &str) -> Result<(), String> {
+	tx.execute("UPDATE kitties SET owner = ?1 WHERE id = ?2", params![owner, kitty_id]).map_err(db_error)?;
+	tx.execute("DELETE FROM listings WHERE kitty_id = ?1", params![kitty_id]).map_err(db_error)?;
+	Ok(())
+}
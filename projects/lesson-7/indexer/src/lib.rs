@@ -0,0 +1,307 @@
+//! Follows a kitties node and keeps the history of every kitty in a local SQLite database,
+//! so applications can query owners, listings and trades without scanning blocks.
+
+pub mod chain;
+pub mod db;
+
+use std::iter;
+use log::{info, warn};
+use substrate_kitties_runtime::{kitties::RawEvent, BlockNumber};
+use crate::chain::{Chain, KittiesEvent, KittyDetails};
+use crate::db::Database;
+
+/// Indexes the blocks of a `Chain` into a `Database`.
+pub struct Indexer<C> {
+	chain: C,
+	db: Database,
+}
+
+impl<C: Chain> Indexer<C> {
+	pub fn new(chain: C, db: Database) -> Self {
+		Indexer { chain, db }
+	}
+
+	/// The database being written.
+	pub fn database(&self) -> &Database {
+		&self.db
+	}
+
+	/// Index every block up to the best one. Blocks that left the best chain since the last
+	/// sync, or while syncing, are rolled back. Returns the number of blocks indexed, counting
+	/// those indexed again after a roll back.
+	pub fn sync(&mut self) -> Result<u32, String> {
+		let finalized = self.chain.finalized_number()?;
+		let best = self.chain.best_number()?;
+		if let Some(last) = self.db.last_block()? {
+			self.roll_back_reorg(last, finalized)?;
+		}
+
+		let mut number = self.db.last_block()?.map_or(0, |last| last + 1);
+		let mut indexed = 0;
+		while number <= best {
+			let hash = match self.chain.block_hash(number)? {
+				Some(hash) => hash,
+				// The best block moved back since we asked
+				None => break,
+			};
+			// The best chain can change between requests, so every block has to follow the
+			// block indexed before it
+			if number > 0 && Some(self.chain.parent_hash(hash)?) != self.db.block_hash(number - 1)? {
+				if !self.roll_back_reorg(number - 1, finalized)? {
+					return Err(format!("Block #{} {} does not follow block #{} of the best chain", number, hash, number - 1));
+				}
+				number = self.db.last_block()?.map_or(0, |last| last + 1);
+				continue;
+			}
+			let mut events = self.chain.kitties_events(hash)?;
+			if number == 0 {
+				events.extend(genesis_events(self.chain.kitties_at(hash)?));
+			}
+			self.db.insert_block(number, hash, &events)?;
+			indexed += 1;
+			number += 1;
+		}
+		if indexed > 0 {
+			info!("Indexed {} blocks up to #{}", indexed, number - 1);
+		}
+		Ok(indexed)
+	}
+
+	/// If the last indexed block is no longer on the best chain, roll back to the finalized
+	/// block, or further back to the last block both still agree on. Returns whether it
+	/// rolled back.
+	fn roll_back_reorg(&mut self, last: BlockNumber, finalized: BlockNumber) -> Result<bool, String> {
+		if self.db.block_hash(last)? == self.chain.block_hash(last)? {
+			return Ok(false);
+		}
+		let mut target = finalized.min(last);
+		while self.db.block_hash(target)? != self.chain.block_hash(target)? {
+			if target == 0 {
+				return Err("The database was indexed from another chain".into());
+			}
+			target -= 1;
+		}
+		warn!("Block #{} left the best chain, rolling back to #{}", last, target);
+		self.db.rollback_to(target)?;
+		Ok(true)
+	}
+}
+
+/// Kitties of the genesis config have no `Created` event, so they are indexed as if block 0
+/// had created them and listed those with a price. The events are stored like real ones, so
+/// a rollback replays them too.
+fn genesis_events(kitties: Vec<(u32, KittyDetails)>) -> Vec<KittiesEvent> {
+	kitties.into_iter().flat_map(|(kitty_id, kitty)| {
+		let listing = kitty.price.map(|price| RawEvent::Ask(kitty.owner.clone(), kitty_id, Some(price)));
+		iter::once(RawEvent::Created(kitty.owner, kitty_id)).chain(listing)
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::Cell;
+	use primitives::crypto::Ss58Codec;
+	use substrate_kitties_runtime::{AccountId, Hash};
+	use crate::chain::ss58_format;
+
+	/// The format the test databases store addresses in, not the runtime's default
+	const FORMAT: u8 = 2;
+
+	type Blocks = Vec<(Hash, Vec<KittiesEvent>)>;
+
+	/// A chain whose best blocks can be replaced to simulate a reorg.
+	struct MockChain {
+		blocks: Blocks,
+		finalized: BlockNumber,
+		/// Blocks that become the best chain once the events of the given block were read
+		pending_reorg: Option<(Hash, Blocks)>,
+		reorged: Cell<bool>,
+		/// The kitties of the genesis config
+		genesis: Vec<(u32, KittyDetails)>,
+	}
+
+	impl MockChain {
+		fn new() -> Self {
+			MockChain {
+				blocks: vec![(block_hash(0, 0), Vec::new())],
+				finalized: 0,
+				pending_reorg: None,
+				reorged: Cell::new(false),
+				genesis: Vec::new(),
+			}
+		}
+
+		fn push(&mut self, fork: u8, events: Vec<KittiesEvent>) {
+			let number = self.blocks.len() as BlockNumber;
+			self.blocks.push((block_hash(fork, number), events));
+		}
+
+		fn best_chain(&self) -> &Blocks {
+			match self.pending_reorg {
+				Some((_, ref fork)) if self.reorged.get() => fork,
+				_ => &self.blocks,
+			}
+		}
+
+		/// A block of either fork with its parent, which the node keeps after a reorg.
+		fn find(&self, hash: Hash) -> Result<(Option<Hash>, &Vec<KittiesEvent>), String> {
+			let forks = Some(&self.blocks).into_iter().chain(self.pending_reorg.as_ref().map(|(_, fork)| fork));
+			for blocks in forks {
+				if let Some(index) = blocks.iter().position(|(block, _)| *block == hash) {
+					let parent = index.checked_sub(1).map(|parent| blocks[parent].0);
+					return Ok((parent, &blocks[index].1));
+				}
+			}
+			Err(format!("Unknown block {}", hash))
+		}
+	}
+
+	impl Chain for MockChain {
+		fn finalized_number(&self) -> Result<BlockNumber, String> {
+			Ok(self.finalized)
+		}
+
+		fn best_number(&self) -> Result<BlockNumber, String> {
+			Ok(self.best_chain().len() as BlockNumber - 1)
+		}
+
+		fn block_hash(&self, number: BlockNumber) -> Result<Option<Hash>, String> {
+			Ok(self.best_chain().get(number as usize).map(|(hash, _)| *hash))
+		}
+
+		fn parent_hash(&self, hash: Hash) -> Result<Hash, String> {
+			self.find(hash)?.0.ok_or_else(|| format!("Block {} has no parent", hash))
+		}
+
+		fn kitties_events(&self, hash: Hash) -> Result<Vec<KittiesEvent>, String> {
+			let events = self.find(hash)?.1.clone();
+			if self.pending_reorg.as_ref().map(|(trigger, _)| *trigger) == Some(hash) {
+				self.reorged.set(true);
+			}
+			Ok(events)
+		}
+
+		fn kitties_at(&self, hash: Hash) -> Result<Vec<(u32, KittyDetails)>, String> {
+			if hash != self.blocks[0].0 {
+				return Err(format!("No kitties state kept for block {}", hash));
+			}
+			Ok(self.genesis.clone())
+		}
+	}
+
+	fn block_hash(fork: u8, number: BlockNumber) -> Hash {
+		let mut hash = [fork; 32];
+		hash[28..].copy_from_slice(&number.to_be_bytes());
+		hash.into()
+	}
+
+	fn account(seed: u8) -> AccountId {
+		AccountId::from_raw([seed; 32])
+	}
+
+	fn address(seed: u8) -> String {
+		account(seed).to_ss58check_with_version(ss58_format(FORMAT))
+	}
+
+	fn indexer() -> Indexer<MockChain> {
+		Indexer::new(MockChain::new(), Database::in_memory(ss58_format(FORMAT)).unwrap())
+	}
+
+	#[test]
+	fn kitties_events_are_indexed() {
+		let mut indexer = indexer();
+		indexer.chain.push(0, vec![RawEvent::Created(account(1), 0), RawEvent::Created(account(1), 1)]);
+		indexer.chain.push(0, vec![RawEvent::Transferred(account(1), account(2), 1), RawEvent::Ask(account(1), 0, Some(500))]);
+		assert_eq!(indexer.sync(), Ok(3));
+		assert_eq!(indexer.db.kitties_count(), Ok(2));
+		assert_eq!(indexer.db.owner(1), Ok(Some(address(2))));
+		assert_eq!(indexer.db.listing_price(0), Ok(Some("500".into())));
+
+		indexer.chain.push(0, vec![RawEvent::Sold(account(1), account(2), 0, 500)]);
+		assert_eq!(indexer.sync(), Ok(1));
+		assert_eq!(indexer.sync(), Ok(0));
+		assert_eq!(indexer.db.owner(0), Ok(Some(address(2))));
+		assert_eq!(indexer.db.listing_price(0), Ok(None));
+		let trades = indexer.db.trades(0).unwrap();
+		assert_eq!(trades.len(), 1);
+		assert_eq!((trades[0].block_number, &trades[0].buyer, &trades[0].price[..]), (3, &address(2), "500"));
+	}
+
+	#[test]
+	fn reorgs_roll_back_to_the_finalized_block() {
+		let mut indexer = indexer();
+		indexer.chain.push(0, vec![RawEvent::Created(account(1), 0)]);
+		indexer.chain.push(0, vec![RawEvent::Ask(account(1), 0, Some(500))]);
+		indexer.chain.push(0, vec![RawEvent::Sold(account(1), account(2), 0, 500)]);
+		indexer.chain.finalized = 1;
+		assert_eq!(indexer.sync(), Ok(4));
+		assert_eq!(indexer.db.owner(0), Ok(Some(address(2))));
+
+		// Blocks 2 and 3 are replaced by a single block on another fork
+		indexer.chain.blocks.truncate(2);
+		indexer.chain.push(1, vec![RawEvent::Transferred(account(1), account(3), 0)]);
+		assert_eq!(indexer.sync(), Ok(1));
+		assert_eq!(indexer.db.last_block(), Ok(Some(2)));
+		assert_eq!(indexer.db.block_hash(2), Ok(Some(block_hash(1, 2))));
+		assert_eq!(indexer.db.owner(0), Ok(Some(address(3))));
+		assert_eq!(indexer.db.listing_price(0), Ok(None));
+		assert_eq!(indexer.db.trades(0), Ok(Vec::new()));
+	}
+
+	#[test]
+	fn reorgs_during_a_sync_are_rolled_back() {
+		let mut indexer = indexer();
+		indexer.chain.push(0, vec![RawEvent::Created(account(1), 0)]);
+		indexer.chain.push(0, vec![RawEvent::Ask(account(1), 0, Some(500))]);
+		indexer.chain.push(0, vec![RawEvent::Sold(account(1), account(2), 0, 500)]);
+		indexer.chain.finalized = 1;
+
+		// Blocks 2 and 3 are replaced right after the events of block 2 were read
+		let mut fork = indexer.chain.blocks[..2].to_vec();
+		fork.push((block_hash(1, 2), vec![RawEvent::Transferred(account(1), account(3), 0)]));
+		fork.push((block_hash(1, 3), Vec::new()));
+		indexer.chain.pending_reorg = Some((block_hash(0, 2), fork));
+
+		// Blocks 0 to 2 of the first fork, then blocks 2 and 3 of the second
+		assert_eq!(indexer.sync(), Ok(5));
+		assert_eq!(indexer.db.last_block(), Ok(Some(3)));
+		assert_eq!(indexer.db.block_hash(2), Ok(Some(block_hash(1, 2))));
+		assert_eq!(indexer.db.block_hash(3), Ok(Some(block_hash(1, 3))));
+		assert_eq!(indexer.db.owner(0), Ok(Some(address(3))));
+		assert_eq!(indexer.db.listing_price(0), Ok(None));
+		assert_eq!(indexer.db.trades(0), Ok(Vec::new()));
+		assert_eq!(indexer.sync(), Ok(0));
+	}
+
+	#[test]
+	fn genesis_kitties_are_indexed() {
+		let mut indexer = indexer();
+		let genesis_kitty = |owner, price| KittyDetails { dna: [owner; 16], owner: account(owner), price, lock: None };
+		indexer.chain.genesis = vec![(0, genesis_kitty(1, Some(500))), (1, genesis_kitty(2, None))];
+		indexer.chain.push(0, vec![RawEvent::Sold(account(1), account(2), 0, 500), RawEvent::Created(account(1), 2)]);
+		indexer.chain.push(0, vec![RawEvent::Transferred(account(2), account(3), 1)]);
+		indexer.chain.finalized = 1;
+		assert_eq!(indexer.sync(), Ok(3));
+		assert_eq!(indexer.db.kitties_count(), Ok(3));
+		assert_eq!(indexer.db.owner(0), Ok(Some(address(2))));
+		assert_eq!(indexer.db.owner(1), Ok(Some(address(3))));
+		assert_eq!(indexer.db.trades(0).unwrap().len(), 1);
+
+		// Rolling back replays them with the rest of the events
+		indexer.chain.blocks.truncate(2);
+		indexer.chain.push(1, Vec::new());
+		assert_eq!(indexer.sync(), Ok(1));
+		assert_eq!(indexer.db.owner(1), Ok(Some(address(2))));
+		assert_eq!(indexer.db.owner(0), Ok(Some(address(2))));
+		assert_eq!(indexer.db.listing_price(0), Ok(None));
+	}
+
+	#[test]
+	fn another_chain_is_rejected() {
+		let mut indexer = indexer();
+		assert_eq!(indexer.sync(), Ok(1));
+		indexer.chain.blocks[0].0 = block_hash(9, 0);
+		assert!(indexer.sync().is_err());
+	}
+}
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use log::{error, info};
+use structopt::StructOpt;
+use kitties_indexer::Indexer;
+use kitties_indexer::chain::HttpRpc;
+use kitties_indexer::db::Database;
+
+/// Index the kitties events of a node into a SQLite database.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "kitties-indexer")]
+struct Opt {
+	/// HTTP RPC endpoint of the node
+	#[structopt(long = "rpc-url", default_value = "http://127.0.0.1:9933")]
+	rpc_url: String,
+
+	/// Database file, created if it does not exist
+	#[structopt(long = "db", default_value = "kitties-index.sqlite", parse(from_os_str))]
+	db: PathBuf,
+
+	/// Milliseconds to wait between polls of the node
+	#[structopt(long = "poll-interval", default_value = "2000")]
+	poll_interval: u64,
+}
+
+fn main() {
+	env_logger::init();
+	let opt = Opt::from_args();
+
+	let chain = HttpRpc::new(&opt.rpc_url);
+	// Addresses are stored in the chain's format, so the node has to be reachable first
+	let addresses = loop {
+		match chain.address_format() {
+			Ok(addresses) => break addresses,
+			Err(e) => error!("{}", e),
+		}
+		thread::sleep(Duration::from_millis(opt.poll_interval));
+	};
+	let db = match Database::open(&opt.db, addresses) {
+		Ok(db) => db,
+		Err(e) => {
+			error!("{}", e);
+			std::process::exit(1);
+		}
+	};
+	info!("Indexing {} into {}", opt.rpc_url, opt.db.display());
+	let mut indexer = Indexer::new(chain, db);
+	loop {
+		// The node may be restarting, so errors are logged and the next poll retries
+		if let Err(e) = indexer.sync() {
+			error!("{}", e);
+		}
+		thread::sleep(Duration::from_millis(opt.poll_interval));
+	}
+}
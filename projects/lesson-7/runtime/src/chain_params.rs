@@ -0,0 +1,34 @@
+//! Consensus timing set in the genesis config, so that one runtime can run networks with
+//! different block times. The values are read at genesis and must not change afterwards.
+//!
+//! The slot duration has to be even, as the timestamp module's minimum period is half of it
+//! and BABE takes its slot duration back as twice that period.
+
+use support::{decl_module, decl_storage};
+
+/// The module's configuration trait.
+pub trait Trait: system::Trait {}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as ChainParams {
+		/// Milliseconds per BABE slot
+		pub SlotDuration get(slot_duration) config(): u64 = crate::MILLISECS_PER_BLOCK;
+		/// BABE slots per epoch
+		pub EpochDuration get(epoch_duration) config(): u64 = crate::EPOCH_DURATION_IN_BLOCKS as u64;
+		/// BABE `c`, where `1 - c` is the probability of a slot without a primary author
+		pub PrimaryProbability get(primary_probability) config(): (u64, u64) = crate::PRIMARY_PROBABILITY;
+	}
+	add_extra_genesis {
+		build(|config: &GenesisConfig| {
+			assert!(config.slot_duration > 0 && config.slot_duration % 2 == 0, "Slot duration must be even and non-zero");
+			assert!(config.epoch_duration > 0, "Epoch duration must be non-zero");
+			let (numerator, denominator) = config.primary_probability;
+			assert!(denominator > 0 && numerator <= denominator, "Primary probability must be between 0 and 1");
+		});
+	}
+}
+
+decl_module! {
+	/// The module declaration.
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {}
+}
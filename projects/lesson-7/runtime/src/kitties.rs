@@ -1,27 +1,251 @@
 use support::{
-	decl_module, decl_storage, decl_event, ensure, StorageValue, StorageMap,
-	Parameter, traits::Currency
+	decl_module, decl_storage, decl_event, decl_error, ensure, StorageValue, StorageMap,
+	Parameter, traits::{Currency, Get}
 };
-use sr_primitives::traits::{SimpleArithmetic, Bounded, Member};
-use codec::{Encode, Decode};
+use sr_primitives::traits::{SimpleArithmetic, Bounded, Member, Hash, CheckedAdd, Zero};
+use sr_primitives::weights::{SimpleDispatchInfo, Weight};
+use codec::{Codec, Encode, Decode};
 use runtime_io::blake2_128;
-use system::ensure_signed;
-use rstd::result;
-use crate::linked_item::{LinkedList, LinkedItem};
+use system::{ensure_signed, ensure_root};
+use rstd::{result, prelude::*};
+#[cfg(feature = "std")]
+use rstd::collections::btree_set::BTreeSet;
+use client::decl_runtime_apis;
+use crate::linked_item::{LinkedList, LinkedItem, LinkedListError};
 
 pub trait Trait: system::Trait {
 	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
 	type KittyIndex: Parameter + Member + SimpleArithmetic + Bounded + Default + Copy;
 	type Currency: Currency<Self::AccountId>;
+	/// Enumerates accounts that may own kitties, used by storage migrations
+	type KnownAccounts: KnownAccounts<Self::AccountId>;
+	/// Source of randomness for kitty DNA
+	type Randomness: Randomness;
+	/// Number of blocks between `commit_create` and the block whose randomness the reveal
+	/// uses. At least 1, and long enough that the randomness is unknown when committing.
+	/// Debug builds check it is at least 1 and shorter than `CommitExpiry` every block
+	type MinRevealDelay: Get<Self::BlockNumber>;
+	/// Number of blocks after which an unrevealed commit expires
+	type CommitExpiry: Get<Self::BlockNumber>;
+	/// Number of blocks after which a transfer offer that was not accepted expires
+	type TransferOfferExpiry: Get<Self::BlockNumber>;
+	/// The longest duration a loan can be requested for
+	type MaxLoanDuration: Get<Self::BlockNumber>;
 }
 
+/// A source of randomness for kitty DNA. Values drawn in the same block for the same subject
+/// are equal, so callers mix whatever must tell them apart into the subject.
+pub trait Randomness {
+	/// Random bytes for `subject`. Different subjects must give unrelated values.
+	fn random(subject: &[u8]) -> [u8; 16];
+}
+
+/// Deterministic randomness for tests, the output only depends on the subject.
+impl Randomness for () {
+	fn random(subject: &[u8]) -> [u8; 16] {
+		blake2_128(subject)
+	}
+}
+
+/// Enumerates the accounts known to the chain. Storage keyed by account is hashed,
+/// so migrations that need to visit every owner use this to find them.
+pub trait KnownAccounts<AccountId> {
+	/// The accounts in batch `batch`, counting from 0. Every batch up to the last one is
+	/// non-empty, the batches after it are empty.
+	fn known_accounts_batch(batch: u32) -> Vec<AccountId>;
+
+	/// Every known account.
+	fn known_accounts() -> Vec<AccountId> {
+		let mut accounts = Vec::new();
+		let mut batch = 0;
+		loop {
+			let next = Self::known_accounts_batch(batch);
+			if next.is_empty() {
+				return accounts;
+			}
+			accounts.extend(next);
+			batch += 1;
+		}
+	}
+}
+
+impl<AccountId> KnownAccounts<AccountId> for () {
+	fn known_accounts_batch(_batch: u32) -> Vec<AccountId> {
+		Vec::new()
+	}
+}
+
+/// A reference price for kitties, for appraisals and loan-to-value checks in other modules.
+pub trait KittyPriceOracle<KittyIndex, Balance> {
+	/// The price the kitty last sold for, or the average sale price of its rarity tier if it
+	/// never sold. None if neither is known.
+	fn reference_price(kitty_id: KittyIndex) -> Option<Balance>;
+}
+
+/// The storage layout version written by this runtime.
+/// Version 0 is the lesson-5 layout, which only recorded ownership in `OwnedKitties`.
+/// Version 1 adds `KittyOwners` and `KittyPrices`.
+/// Version 2 adds `KittyLocks`.
+/// Version 3 adds `Listings`.
+pub const CURRENT_STORAGE_VERSION: u32 = 3;
+
+/// The most kitties a block migrates towards the next storage version, so that migrating a
+/// large chain is spread over several blocks.
+pub const MIGRATION_BATCH: u32 = weights::MIGRATION_BUDGET / weights::MIGRATE_KITTY;
+
+/// The most commitments, rentals, loans or transfer offers that can expire in one block, which
+/// bounds the work of `on_finalize`.
+pub const MAX_EXPIRIES_PER_BLOCK: usize = 64;
+
+/// Subject of the randomness drawn for `reveal_create`, see `RevealRandomness`.
+pub const REVEAL_SUBJECT: &[u8] = b"kitties/reveal";
+
+/// The most listings returned by one `listings_page` call.
+pub const MAX_LISTINGS_PAGE: u32 = 100;
+
+/// The most kitties returned by one `owned_page` call.
+pub const MAX_OWNED_PAGE: u32 = 100;
+
+/// The number of most recent sales kept in each kitty's `SaleHistory`.
+pub const MAX_SALE_HISTORY: usize = 8;
+
+/// Each sale moves the rolling average of its tier by 1 / `SALE_AVERAGE_WINDOW` of the difference.
+pub const SALE_AVERAGE_WINDOW: u32 = 8;
+
+/// The number of rarity tiers, see `Kitty::rarity_tier`.
+pub const RARITY_TIERS: u8 = 4;
+
 type BalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::Balance;
 
+/// Where a storage migration spread over several blocks continues in the next block.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+pub enum MigrationCursor<KittyIndex> {
+	/// Walking the owned lists of known accounts batch `batch`, from the account at `account`,
+	/// after `after` in its list or from the start of the list when `after` is None
+	Accounts { batch: u32, account: u32, after: Option<KittyIndex> },
+	/// Visiting the kitties from this index on
+	Kitties(KittyIndex),
+}
+
 #[derive(Encode, Decode)]
 pub struct Kitty(pub [u8; 16]);
 
+impl Kitty {
+	/// How unusual the DNA is, from 0 for the most common to `RARITY_TIERS - 1`. Random DNA
+	/// has about half of its bits set, the further from half the rarer the kitty.
+	pub fn rarity_tier(&self) -> u8 {
+		let ones: u32 = self.0.iter().map(|byte| byte.count_ones()).sum();
+		let distance = if ones > 64 { ones - 64 } else { 64 - ones };
+		(distance / 8).min(u32::from(RARITY_TIERS - 1)) as u8
+	}
+}
+
+/// Everything known about a kitty at a glance, for tools outside the runtime.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+pub struct KittyDetails<AccountId, Balance> {
+	/// The kitty's DNA
+	pub dna: [u8; 16],
+	/// The owner, which is not the holder during a rental
+	pub owner: AccountId,
+	/// The asking price, None if not for sale
+	pub price: Option<Balance>,
+	/// Why the kitty is locked, None if it is free to move
+	pub lock: Option<LockReason>,
+}
+
+/// A completed sale of a kitty.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+pub struct Sale<AccountId, BlockNumber, Balance> {
+	/// The asking price paid
+	pub price: Balance,
+	/// The block the sale happened in
+	pub block: BlockNumber,
+	/// The new owner
+	pub buyer: AccountId,
+	/// The previous owner
+	pub seller: AccountId,
+}
+
+/// Why a kitty is locked. A locked kitty can not be transferred, and can not be locked
+/// again for another reason until it is unlocked.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub enum LockReason {
+	/// Listed for sale, see `KittyPrices`
+	Listed,
+	/// Up for auction
+	Auction,
+	/// Pledged as collateral, see `KittyLoans`
+	Loan,
+	/// Rented out or offered for rent, see `KittyRentals`
+	Rental,
+	/// Carrying a litter
+	Pregnant,
+	/// Offered to another account, see `TransferOffers`
+	Offered,
+}
+
+impl LockReason {
+	/// Whether the kitty can still be used for breeding while locked for this reason.
+	pub fn allows_breeding(&self) -> bool {
+		match self {
+			LockReason::Listed | LockReason::Rental => true,
+			LockReason::Auction | LockReason::Loan | LockReason::Pregnant | LockReason::Offered => false,
+		}
+	}
+}
+
+/// The parts of the module that root can pause with `set_paused`.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+	/// `create`, `commit_create` and `reveal_create`
+	Creation,
+	/// `breed`
+	Breeding,
+	/// Moving kitties and listing, renting or pledging them
+	Trading,
+}
+
+/// A kitty lent to a borrower until a given block.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+pub struct Rental<AccountId, BlockNumber, Balance> {
+	/// The account holding the kitty during the rental
+	pub borrower: AccountId,
+	/// The block at the end of which the kitty returns to its owner
+	pub until: BlockNumber,
+	/// The fee the borrower pays the owner to start the rental
+	pub fee: Balance,
+	/// Whether the borrower accepted and paid. Until then the rental is only an offer
+	pub active: bool,
+}
+
+/// A loan secured by a kitty. The kitty is locked from the request until the loan is repaid
+/// or the collateral is seized.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+pub struct Loan<AccountId, BlockNumber, Balance> {
+	/// The amount lent to the kitty owner
+	pub principal: Balance,
+	/// The amount owed on top of the principal
+	pub interest: Balance,
+	/// Number of blocks the owner has to repay once the loan is funded
+	pub duration: BlockNumber,
+	/// The lender and the block at the end of which the collateral goes to them.
+	/// `None` while the request is not funded yet
+	pub funded: Option<(AccountId, BlockNumber)>,
+}
+
+type SaleOf<T> = Sale<<T as system::Trait>::AccountId, <T as system::Trait>::BlockNumber, BalanceOf<T>>;
+type LoanOf<T> = Loan<<T as system::Trait>::AccountId, <T as system::Trait>::BlockNumber, BalanceOf<T>>;
+type RentalOf<T> = Rental<<T as system::Trait>::AccountId, <T as system::Trait>::BlockNumber, BalanceOf<T>>;
 type KittyLinkedItem<T> = LinkedItem<<T as Trait>::KittyIndex>;
 type OwnedKittiesList<T> = LinkedList<OwnedKitties<T>, <T as system::Trait>::AccountId, <T as Trait>::KittyIndex>;
+type ListingsList<T> = LinkedList<Listings<T>, Option<<T as system::Trait>::AccountId>, <T as Trait>::KittyIndex>;
 
 decl_storage! {
 	trait Store for Module<T: Trait> as Kitties {
@@ -35,13 +259,81 @@ decl_storage! {
 		/// Get kitty owner
 		pub KittyOwners get(kitty_owner): map T::KittyIndex => Option<T::AccountId>;
 		/// Get kitty price. None means not for sale.
-		pub KittyPrices get(kitty_price): map T::KittyIndex => Option<BalanceOf<T>>
+		pub KittyPrices get(kitty_price): map T::KittyIndex => Option<BalanceOf<T>>;
+		/// Kitties for sale, linked like `OwnedKitties`. The `None` list holds every kitty for
+		/// sale, the `Some(seller)` lists the kitties listed by each seller.
+		pub Listings get(listings): map (Option<T::AccountId>, Option<T::KittyIndex>) => Option<KittyLinkedItem<T>>;
+		/// Changes on every `ask`, so that a buyer can tell the listing they read is still current
+		pub ListingNonces get(listing_nonce): map T::KittyIndex => u32;
+		/// The most recent sales of a kitty, oldest first, at most `MAX_SALE_HISTORY`
+		pub SaleHistory get(sale_history): map T::KittyIndex => Vec<SaleOf<T>>;
+		/// Rolling average sale price of each rarity tier. None until a kitty of the tier sells
+		pub AverageSalePrice get(average_sale_price): map u8 => Option<BalanceOf<T>>;
+		/// Why a kitty is locked. None means it is free to move
+		pub KittyLocks get(kitty_lock): map T::KittyIndex => Option<LockReason>;
+
+		/// Pending mint commitments, the commitment hash and the block it was made in
+		pub CreateCommits get(create_commit): map T::AccountId => Option<(T::Hash, T::BlockNumber)>;
+		/// Accounts whose commitments expire at the end of the given block
+		pub CommitExpiries get(commit_expiries): map T::BlockNumber => Vec<T::AccountId>;
+		/// Randomness drawn at the start of a block that pending commitments reveal against.
+		/// A commitment made at block `n` reveals against block `n + MinRevealDelay`
+		pub RevealRandomness get(reveal_randomness): map T::BlockNumber => Option<[u8; 16]>;
+		/// Number of pending commitments that reveal against the given block
+		pub RevealTargets get(reveal_targets): map T::BlockNumber => u32;
+
+		/// Rentals and rental offers, the owner stays in `KittyOwners` while the borrower holds the kitty
+		pub KittyRentals get(kitty_rental): map T::KittyIndex => Option<RentalOf<T>>;
+		/// Kitties whose rental ends at the end of the given block
+		pub RentalExpiries get(rental_expiries): map T::BlockNumber => Vec<T::KittyIndex>;
+
+		/// Loans secured by a kitty, including requests that are not funded yet
+		pub KittyLoans get(kitty_loan): map T::KittyIndex => Option<LoanOf<T>>;
+		/// Kitties whose loan is due at the end of the given block
+		pub LoanDeadlines get(loan_deadlines): map T::BlockNumber => Vec<T::KittyIndex>;
+
+		/// Pending transfer offers, the recipient and the block the offer expires at
+		pub TransferOffers get(transfer_offer): map T::KittyIndex => Option<(T::AccountId, T::BlockNumber)>;
+		/// Kitties whose transfer offer expires at the end of the given block
+		pub TransferOfferExpiries get(transfer_offer_expiries): map T::BlockNumber => Vec<T::KittyIndex>;
+
+		/// Activities paused by root
+		pub Paused get(is_paused): map Activity => bool;
+		/// Accounts blocked by root from creating, breeding and trading kitties
+		pub BlockedAccounts get(is_blocked): map T::AccountId => bool;
+
+		/// The storage layout version, see `CURRENT_STORAGE_VERSION`
+		pub StorageVersion get(storage_version): u32;
+		/// Progress of the migration to the next storage version. None when it has not started
+		pub MigrationProgress get(migration_progress): Option<MigrationCursor<T::KittyIndex>>;
+	}
+	add_extra_genesis {
+		/// Kitties that exist from genesis, with their owner, DNA and asking price.
+		/// They get indexes in order, starting from 0.
+		config(kitties): Vec<(T::AccountId, [u8; 16], Option<BalanceOf<T>>)>;
+		build(|config: &GenesisConfig<T>| {
+			for (owner, dna, price) in &config.kitties {
+				let kitty_id = <Module<T>>::kitties_count();
+				<Module<T>>::insert_kitty(owner, kitty_id, Kitty(*dna))
+					.ok().expect("Genesis kitties get new indexes, so they are not owned yet; qed");
+				if let Some(price) = price {
+					<Module<T>>::index_listing(owner, kitty_id)
+						.ok().expect("Genesis kitties get new indexes, so they are not listed yet; qed");
+					<KittyLocks<T>>::insert(kitty_id, LockReason::Listed);
+					<KittyPrices<T>>::insert(kitty_id, price);
+				}
+			}
+			// Genesis storage is written in the current layout
+			StorageVersion::put(CURRENT_STORAGE_VERSION);
+		});
 	}
 }
 
 decl_event!(
 	pub enum Event<T> where
 		<T as system::Trait>::AccountId,
+		<T as system::Trait>::Hash,
+		<T as system::Trait>::BlockNumber,
 		<T as Trait>::KittyIndex,
 		Balance = BalanceOf<T>,
 	{
@@ -53,16 +345,322 @@ decl_event!(
 		Ask(AccountId, KittyIndex, Option<Balance>),
 		/// A kitty is sold. (from, to, kitty_id, price)
 		Sold(AccountId, AccountId, KittyIndex, Balance),
+		/// A kitty mint is committed to. (owner, commitment)
+		CreateCommitted(AccountId, Hash),
+		/// A mint commitment expired without being revealed. (owner)
+		CreateCommitExpired(AccountId),
+		/// A kitty is offered for rent. (owner, borrower, kitty_id, until, fee)
+		LendOffered(AccountId, AccountId, KittyIndex, BlockNumber, Balance),
+		/// A rental offer is accepted and paid. (borrower, kitty_id)
+		Borrowed(AccountId, KittyIndex),
+		/// A rented kitty is back with its owner, or the offer was withdrawn. (owner, kitty_id)
+		Returned(AccountId, KittyIndex),
+		/// A loan secured by a kitty is requested. (owner, kitty_id, principal, interest, duration)
+		LoanRequested(AccountId, KittyIndex, Balance, Balance, BlockNumber),
+		/// A loan request is withdrawn. (owner, kitty_id)
+		LoanCancelled(AccountId, KittyIndex),
+		/// A loan is funded. (lender, kitty_id, deadline)
+		LoanFunded(AccountId, KittyIndex, BlockNumber),
+		/// A loan is repaid and the kitty unlocked. (owner, lender, kitty_id)
+		LoanRepaid(AccountId, AccountId, KittyIndex),
+		/// An unpaid loan's collateral went to the lender. (owner, lender, kitty_id)
+		CollateralSeized(AccountId, AccountId, KittyIndex),
+		/// A kitty is offered to another account. (from, to, kitty_id, expires_at)
+		TransferOffered(AccountId, AccountId, KittyIndex, BlockNumber),
+		/// A transfer offer is withdrawn, or expired without being accepted. (from, kitty_id)
+		TransferOfferEnded(AccountId, KittyIndex),
+		/// Root paused or resumed an activity. (activity, paused)
+		PauseSet(Activity, bool),
+		/// Root moved a kitty. (from, to, kitty_id)
+		ForceTransferred(AccountId, AccountId, KittyIndex),
+		/// Root took a kitty off sale. (owner, kitty_id)
+		ForceDelisted(AccountId, KittyIndex),
+		/// Root blocked or unblocked an account. (account, blocked)
+		BlockListUpdated(AccountId, bool),
+		/// A block ran a batch of a storage migration. (version migrated to, kitties migrated, weight)
+		StorageMigrated(u32, u32, Weight),
 	}
 );
 
+/// Weights of the kitties dispatchables and hooks, derived from the storage they touch.
+///
+/// One unit of weight is a microsecond of execution, so the runtime's `MaximumBlockWeight` of
+/// 1_000_000 is one second of a six second slot. The storage and base costs are estimates of
+/// RocksDB access and extrinsic overhead rather than measured values. Storage dominates
+/// every call here, `calls_write_within_their_weight` checks the writes of each call fit its
+/// weight and the ignored `benchmark_weights` test reports how long they take.
+pub mod weights {
+	use sr_primitives::weights::Weight;
+
+	/// Fixed overhead of any call, covering decoding, signature and origin checks.
+	pub const BASE: Weight = 125;
+	/// A single storage read.
+	pub const READ: Weight = 25;
+	/// A single storage write.
+	pub const WRITE: Weight = 100;
+	/// Depositing an event appends to the system events.
+	pub const EVENT: Weight = WRITE;
+
+	/// `LinkedList::append`: existence check, head and tail reads; head, tail and item writes.
+	pub const LIST_APPEND: Weight = 3 * READ + 3 * WRITE;
+	/// `LinkedList::remove`: item take, prev and next reads; item, prev and next writes.
+	pub const LIST_REMOVE: Weight = 3 * READ + 3 * WRITE;
+	/// `Currency::transfer`: both free balances and the existential deposit check, both balances written.
+	pub const CURRENCY_TRANSFER: Weight = 3 * READ + 2 * WRITE;
+	/// The pause flag and the block list entries of up to two accounts, checked by every
+	/// call that can be paused.
+	pub const GUARDS: Weight = 3 * READ;
+	/// Adding to an expiry queue: the queue read for its length, then written.
+	pub const SCHEDULE: Weight = READ + WRITE;
+	/// Taking an item that ended early off its expiry queue: the queue read and written.
+	pub const UNSCHEDULE: Weight = READ + WRITE;
+
+	/// Releasing the claim of a commitment on its reveal randomness: the count read, then the
+	/// count written or it and the randomness removed.
+	pub const RELEASE_REVEAL_TARGET: Weight = READ + 2 * WRITE;
+	/// Expiring a commitment in `on_finalize`: the commitment read and removed, its reveal
+	/// target released, and the event.
+	pub const EXPIRE_COMMIT: Weight = READ + WRITE + RELEASE_REVEAL_TARGET + EVENT;
+	/// Ending a rental in `on_finalize`: rental, lock and owner read, rental and lock removed,
+	/// and the event.
+	pub const EXPIRE_RENTAL: Weight = 3 * READ + 2 * WRITE + EVENT;
+	/// Seizing collateral in `on_finalize`: loan and owner read, loan and lock removed, and the
+	/// kitty moved like `transfer` does after its ownership check.
+	pub const SEIZE_COLLATERAL: Weight = 2 * READ + 2 * WRITE
+		+ 3 * READ + LIST_REMOVE + LIST_APPEND + WRITE + EVENT;
+	/// Withdrawing a transfer offer in `on_finalize`: offer, lock and owner read, offer and
+	/// lock removed, and the event.
+	pub const EXPIRE_TRANSFER_OFFER: Weight = 3 * READ + 2 * WRITE + EVENT;
+	/// Taking the four expiry queues of a block in `on_finalize`, paid by no call. The
+	/// `AvailableBlockRatio` headroom covers it.
+	pub const ON_FINALIZE_BASE: Weight = 4 * (READ + WRITE);
+	/// The most `on_finalize` can cost, with every queue of the block full.
+	pub const ON_FINALIZE_MAX: Weight = ON_FINALIZE_BASE + super::MAX_EXPIRIES_PER_BLOCK as Weight
+		* (EXPIRE_COMMIT + EXPIRE_RENTAL + SEIZE_COLLATERAL + EXPIRE_TRANSFER_OFFER);
+
+	/// `create`: `KittiesCount` plus the randomness, extrinsic index and block number, then
+	/// a list append and `Kitties`, `KittiesCount` and `KittyOwners` writes.
+	pub const CREATE: Weight = BASE + GUARDS + 4 * READ + LIST_APPEND + 3 * WRITE + EVENT;
+	/// `breed`: both parents with their locks, owners and rentals on top of everything `create` does.
+	pub const BREED: Weight = CREATE + 8 * READ;
+	/// `transfer`: ownership, lock and both list checks, then a list remove, a list append
+	/// and the `KittyOwners` write.
+	pub const TRANSFER: Weight = BASE + GUARDS + 4 * READ + LIST_REMOVE + LIST_APPEND + WRITE + EVENT;
+	/// `commit_create`: pending commit check and block number, then the commit written, the
+	/// expiry scheduled and the reveal target counted. Pays for its expiry in `on_finalize` and
+	/// for drawing the reveal randomness in `on_initialize` up front.
+	pub const COMMIT_CREATE: Weight = BASE + GUARDS + 2 * READ + WRITE + SCHEDULE + 2 * READ + 2 * WRITE + EVENT
+		+ EXPIRE_COMMIT;
+	/// `reveal_create`: the commit and reveal randomness read, the commit removed and
+	/// unscheduled and the reveal target released on top of everything `create` does.
+	pub const REVEAL_CREATE: Weight = CREATE + 2 * READ + WRITE + UNSCHEDULE + RELEASE_REVEAL_TARGET;
+	/// `ask`: ownership, lock, nonce and both listing checks, then two listing appends and the
+	/// `KittyPrices`, `KittyLocks` and `ListingNonces` writes.
+	pub const ASK: Weight = BASE + GUARDS + 5 * READ + 2 * LIST_APPEND + 3 * WRITE + EVENT;
+	/// `buy`: owner, price, nonce and all four list checks, the currency transfer, two listing
+	/// removes, the `KittyPrices` and `KittyLocks` removals, the kitty, sale history and tier
+	/// average updated and everything `transfer` does after its ownership check.
+	pub const BUY: Weight = BASE + GUARDS + 7 * READ + CURRENCY_TRANSFER + 2 * LIST_REMOVE + 2 * WRITE
+		+ 3 * READ + 2 * WRITE
+		+ 3 * READ + LIST_REMOVE + LIST_APPEND + WRITE + EVENT;
+	/// `lend`: ownership, lock and block number, then the rental and lock written and the
+	/// expiry scheduled. Pays for the rental ending in `on_finalize` up front.
+	pub const LEND: Weight = BASE + GUARDS + 3 * READ + 2 * WRITE + SCHEDULE + EVENT + EXPIRE_RENTAL;
+	/// `borrow`: the rental and owner, the fee transfer and the rental written.
+	pub const BORROW: Weight = BASE + GUARDS + 2 * READ + CURRENCY_TRANSFER + WRITE + EVENT;
+	/// `return_kitty`: the rental, owner and lock, then the rental and lock removed and the
+	/// rental unscheduled.
+	pub const RETURN_KITTY: Weight = BASE + 3 * READ + 2 * WRITE + UNSCHEDULE + EVENT;
+	/// `request_loan`: ownership and lock checks, then the loan and lock written.
+	pub const REQUEST_LOAN: Weight = BASE + GUARDS + 2 * READ + 2 * WRITE + EVENT;
+	/// `cancel_loan_request`: ownership, loan and lock, then the loan and lock removed.
+	pub const CANCEL_LOAN_REQUEST: Weight = BASE + 3 * READ + 2 * WRITE + EVENT;
+	/// `fund_loan`: the loan, owner and block number, the principal transfer, then the loan
+	/// written and the deadline scheduled. Pays for seizing the collateral in `on_finalize` up front.
+	pub const FUND_LOAN: Weight = BASE + GUARDS + 3 * READ + CURRENCY_TRANSFER + WRITE + SCHEDULE + EVENT
+		+ SEIZE_COLLATERAL;
+	/// `repay_loan`: ownership, loan and lock, the repayment transfer, then the loan and lock
+	/// removed and the deadline unscheduled.
+	pub const REPAY_LOAN: Weight = BASE + 3 * READ + CURRENCY_TRANSFER + 2 * WRITE + UNSCHEDULE + EVENT;
+	/// `offer_transfer`: ownership, lock and block number, then the lock and offer written
+	/// and the expiry scheduled. Pays for the offer expiring in `on_finalize` up front.
+	pub const OFFER_TRANSFER: Weight = BASE + GUARDS + 2 * READ + 2 * WRITE + SCHEDULE + EVENT
+		+ EXPIRE_TRANSFER_OFFER;
+	/// `accept_transfer`: offer, owner and both list checks, the offer and lock removed, the
+	/// offer unscheduled and everything `transfer` does after its ownership check.
+	pub const ACCEPT_TRANSFER: Weight = BASE + GUARDS + 4 * READ + 2 * WRITE + UNSCHEDULE
+		+ 3 * READ + LIST_REMOVE + LIST_APPEND + WRITE + EVENT;
+	/// `cancel_transfer`: ownership, offer and lock, then the offer and lock removed and the
+	/// offer unscheduled.
+	pub const CANCEL_TRANSFER: Weight = BASE + 3 * READ + 2 * WRITE + UNSCHEDULE + EVENT;
+	/// `set_paused`: the pause flag written.
+	pub const SET_PAUSED: Weight = BASE + WRITE + EVENT;
+	/// `force_transfer`: ownership, lock and both list checks, at worst a listing withdrawn like
+	/// `force_delist` does, then the lock removed, a list remove, a list append and the
+	/// `KittyOwners` write.
+	pub const FORCE_TRANSFER: Weight = BASE + 4 * READ + 2 * READ + 2 * LIST_REMOVE + 3 * WRITE
+		+ WRITE + LIST_REMOVE + LIST_APPEND + WRITE + EVENT;
+	/// `force_delist`: owner, lock and both listing checks, then two listing removes and the
+	/// `KittyPrices`, `KittyLocks` and `ListingNonces` writes.
+	pub const FORCE_DELIST: Weight = BASE + 4 * READ + 2 * LIST_REMOVE + 3 * WRITE + EVENT;
+	/// `set_blocked`: the block list entry written.
+	pub const SET_BLOCKED: Weight = BASE + WRITE + EVENT;
+
+	/// The share of a block's weight a storage migration batch may use.
+	pub const MIGRATION_BUDGET: Weight = 250_000;
+	/// Migrating one kitty, at worst the version 3 listing: price and owner reads and two
+	/// listing appends.
+	pub const MIGRATE_KITTY: Weight = 2 * READ + 2 * LIST_APPEND;
+
+	/// A migration batch that migrated `kitties`: the version and cursor read and written,
+	/// the kitties and the event.
+	pub fn migration_batch(kitties: u32) -> Weight {
+		BASE + 2 * READ + 2 * WRITE + kitties * MIGRATE_KITTY + EVENT
+	}
+}
+
+decl_error! {
+	/// Error for the kitties module.
+	pub enum Error {
+		/// The kitty id counter has reached the maximum `KittyIndex`
+		KittiesCountOverflow,
+		/// No kitty exists with the given id
+		InvalidKittyId,
+		/// The sender does not own the kitty
+		NotOwner,
+		/// Breeding requires two different parents
+		RequireDifferentParent,
+		/// The kitty has no price set
+		NotForSale,
+		/// The offered price is below the asking price
+		PriceTooLow,
+		/// The kitty is already linked into the owner's list
+		KittyAlreadyInList,
+		/// The kitty is not linked into the owner's list
+		KittyNotInList,
+		/// The sender already has an unrevealed mint commitment
+		CommitAlreadyPending,
+		/// The sender has no mint commitment to reveal
+		NoPendingCommit,
+		/// The reveal came before `MinRevealDelay` blocks passed
+		RevealTooEarly,
+		/// The commitment is older than `CommitExpiry` blocks
+		CommitExpired,
+		/// The salt does not match the commitment
+		InvalidReveal,
+		/// The kitty is rented out or offered for rent
+		KittyRented,
+		/// The kitty is listed for sale
+		KittyForSale,
+		/// The rental must end after the current block
+		InvalidRentalPeriod,
+		/// The kitty has no rental or rental offer
+		NoRental,
+		/// The sender is not the borrower of the rental
+		NotBorrower,
+		/// The rental offer was already accepted
+		RentalAlreadyActive,
+		/// The kitty is pledged as collateral for a loan
+		KittyPledged,
+		/// The kitty has no loan or loan request
+		NoLoan,
+		/// The loan is already funded
+		LoanAlreadyFunded,
+		/// The loan is not funded yet
+		LoanNotFunded,
+		/// Principal plus interest does not fit in a balance
+		LoanOverflow,
+		/// The kitty is up for auction
+		KittyInAuction,
+		/// The kitty is pregnant
+		KittyPregnant,
+		/// The kitty is offered to another account
+		KittyOffered,
+		/// The kitty has no pending transfer offer
+		NoTransferOffer,
+		/// The transfer offer is for another account
+		NotRecipient,
+		/// The listing changed since the buyer read it
+		ListingChanged,
+		/// The sender already owns the kitty
+		BuyerIsOwner,
+		/// The activity is paused by root
+		ActivityPaused,
+		/// The account is blocked by root
+		AccountBlocked,
+		/// Too many commitments, rentals, loans or offers already expire in that block
+		ExpiryQueueFull,
+		/// No randomness was drawn for the commitment, which predates reveal randomness and can only expire
+		NoRevealRandomness,
+		/// The loan duration must be at least a block and at most `MaxLoanDuration`
+		InvalidLoanDuration,
+		/// The loan deadline is past the last block number
+		LoanDeadlineOverflow,
+		/// The loan terms differ from the ones the lender agreed to
+		LoanTermsChanged,
+	}
+}
+
+impl From<LockReason> for Error {
+	fn from(reason: LockReason) -> Self {
+		match reason {
+			LockReason::Listed => Error::KittyForSale,
+			LockReason::Auction => Error::KittyInAuction,
+			LockReason::Loan => Error::KittyPledged,
+			LockReason::Rental => Error::KittyRented,
+			LockReason::Pregnant => Error::KittyPregnant,
+			LockReason::Offered => Error::KittyOffered,
+		}
+	}
+}
+
+impl From<LinkedListError> for Error {
+	fn from(err: LinkedListError) -> Self {
+		match err {
+			LinkedListError::AlreadyPresent => Error::KittyAlreadyInList,
+			LinkedListError::NotFound => Error::KittyNotInList,
+		}
+	}
+}
+
 decl_module! {
 	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		type Error = Error;
+
 		fn deposit_event() = default;
 
+		fn on_initialize(n: T::BlockNumber) {
+			// Commitments could never be revealed otherwise
+			debug_assert!(
+				!T::MinRevealDelay::get().is_zero() && T::MinRevealDelay::get() < T::CommitExpiry::get(),
+				"MinRevealDelay has to be at least 1 and shorter than CommitExpiry"
+			);
+
+			// After a runtime upgrade, each block brings a batch of older storage up to date
+			if Self::storage_version() < CURRENT_STORAGE_VERSION {
+				Self::migrate_batch();
+			}
+
+			// Fix the randomness of this block for the commitments revealing against it
+			if Self::reveal_targets(n) > 0 {
+				<RevealRandomness<T>>::insert(n, (REVEAL_SUBJECT, n).using_encoded(T::Randomness::random));
+			}
+		}
+
+		// The calls that schedule expiries pay for them, and each block's queues are bounded
+		// by `MAX_EXPIRIES_PER_BLOCK`, so this costs at most `weights::ON_FINALIZE_MAX`
+		fn on_finalize(n: T::BlockNumber) {
+			Self::expire_commits(n);
+			Self::expire_rentals(n);
+			Self::seize_overdue_collateral(n);
+			Self::expire_transfer_offers(n);
+		}
+
 		/// Create a new kitty
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::CREATE)]
 		pub fn create(origin) {
 			let sender = ensure_signed(origin)?;
+			Self::ensure_active(Activity::Creation, &[&sender])?;
 			let kitty_id = Self::next_kitty_id()?;
 
 			// Generate a random 128bit value
@@ -70,14 +668,16 @@ decl_module! {
 
 			// Create and store kitty
 			let kitty = Kitty(dna);
-			Self::insert_kitty(&sender, kitty_id, kitty);
+			Self::insert_kitty(&sender, kitty_id, kitty)?;
 
 			Self::deposit_event(RawEvent::Created(sender, kitty_id));
 		}
 
 		/// Breed kitties
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::BREED)]
 		pub fn breed(origin, kitty_id_1: T::KittyIndex, kitty_id_2: T::KittyIndex) {
 			let sender = ensure_signed(origin)?;
+			Self::ensure_active(Activity::Breeding, &[&sender])?;
 
 			let new_kitty_id = Self::do_breed(&sender, kitty_id_1, kitty_id_2)?;
 
@@ -85,53 +685,433 @@ decl_module! {
 		}
 
 		/// Transfer a kitty to new owner
- 		pub fn transfer(origin, to: T::AccountId, kitty_id: T::KittyIndex) {
- 			let sender = ensure_signed(origin)?;
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::TRANSFER)]
+		pub fn transfer(origin, to: T::AccountId, kitty_id: T::KittyIndex) {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_active(Activity::Trading, &[&sender, &to])?;
 
-  			ensure!(<OwnedKitties<T>>::exists(&(sender.clone(), Some(kitty_id))), "Only owner can transfer kitty");
+			ensure!(<OwnedKitties<T>>::exists(&(sender.clone(), Some(kitty_id))), Error::NotOwner);
 
-			Self::do_transfer(&sender, &to, kitty_id);
+			Self::do_transfer(&sender, &to, kitty_id)?;
 
 			Self::deposit_event(RawEvent::Transferred(sender, to, kitty_id));
 		}
 
 		/// Set a price for a kitty for sale
 		/// None to delist the kitty
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::ASK)]
 		pub fn ask(origin, kitty_id: T::KittyIndex, price: Option<BalanceOf<T>>) {
 			let sender = ensure_signed(origin)?;
 
-			ensure!(<OwnedKitties<T>>::exists(&(sender.clone(), Some(kitty_id))), "Only owner can set price for kitty");
+			ensure!(<OwnedKitties<T>>::exists(&(sender.clone(), Some(kitty_id))), Error::NotOwner);
+
+			// Taking a kitty off sale is always allowed
+			if price.is_some() {
+				Self::ensure_active(Activity::Trading, &[&sender])?;
+			}
 
+			let listed = Self::kitty_lock(kitty_id) == Some(LockReason::Listed);
 			if let Some(ref price) = price {
+				// Changing the price of a listed kitty keeps its lock and place in the listings
+				if !listed {
+					Self::ensure_unlocked(kitty_id)?;
+					Self::index_listing(&sender, kitty_id)?;
+					<KittyLocks<T>>::insert(kitty_id, LockReason::Listed);
+				}
 				<KittyPrices<T>>::insert(kitty_id, price);
-			} else {
+			} else if listed {
+				Self::unindex_listing(&sender, kitty_id)?;
+				Self::unlock_kitty(kitty_id, LockReason::Listed);
 				<KittyPrices<T>>::remove(kitty_id);
 			}
+			<ListingNonces<T>>::mutate(kitty_id, |nonce| *nonce = nonce.wrapping_add(1));
 
 			Self::deposit_event(RawEvent::Ask(sender, kitty_id, price));
 		}
 
-		pub fn buy(origin, kitty_id: T::KittyIndex, price: BalanceOf<T>) {
+		/// Buy a kitty for sale, paying the asking price to its owner.
+		/// Fails if the listing is no longer at `nonce` or asks more than `max_price`.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::BUY)]
+		pub fn buy(origin, kitty_id: T::KittyIndex, nonce: u32, max_price: BalanceOf<T>) {
 			let sender = ensure_signed(origin)?;
 
 			let owner = Self::kitty_owner(kitty_id);
-			ensure!(owner.is_some(), "Kitty does not exist");
+			ensure!(owner.is_some(), Error::InvalidKittyId);
 			let owner = owner.unwrap();
+			ensure!(owner != sender, Error::BuyerIsOwner);
+			Self::ensure_active(Activity::Trading, &[&sender, &owner])?;
 
 			let kitty_price = Self::kitty_price(kitty_id);
-			ensure!(kitty_price.is_some(), "Kitty not for sale");
+			ensure!(kitty_price.is_some(), Error::NotForSale);
+			ensure!(Self::listing_nonce(kitty_id) == nonce, Error::ListingChanged);
 
 			let kitty_price = kitty_price.unwrap();
-			ensure!(price >= kitty_price, "Price is too low");
+			ensure!(max_price >= kitty_price, Error::PriceTooLow);
+
+			// Make sure the kitty can actually move before any funds do. It is locked as listed,
+			// which this sale releases.
+			Self::ensure_can_relink(&owner, &sender, kitty_id)?;
+			Self::ensure_listing_indexed(&owner, kitty_id)?;
 
 			T::Currency::transfer(&sender, &owner, kitty_price)?;
 
+			Self::unindex_listing(&owner, kitty_id)?;
 			<KittyPrices<T>>::remove(kitty_id);
+			Self::unlock_kitty(kitty_id, LockReason::Listed);
 
-			Self::do_transfer(&owner, &sender, kitty_id);
+			Self::do_transfer(&owner, &sender, kitty_id)?;
+			Self::record_sale(kitty_id, Sale {
+				price: kitty_price,
+				block: <system::Module<T>>::block_number(),
+				buyer: sender.clone(),
+				seller: owner.clone(),
+			});
 
 			Self::deposit_event(RawEvent::Sold(owner, sender, kitty_id, kitty_price));
 		}
+
+		/// Commit to minting a kitty without revealing the salt its DNA is derived from.
+		/// The commitment is the hash of `(sender, salt)`.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::COMMIT_CREATE)]
+		pub fn commit_create(origin, commitment: T::Hash) {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_active(Activity::Creation, &[&sender])?;
+
+			ensure!(!<CreateCommits<T>>::exists(&sender), Error::CommitAlreadyPending);
+
+			let now = <system::Module<T>>::block_number();
+			let expires_at = now + T::CommitExpiry::get();
+			ensure!(Self::commit_expiries(expires_at).len() < MAX_EXPIRIES_PER_BLOCK, Error::ExpiryQueueFull);
+
+			<CreateCommits<T>>::insert(&sender, (commitment, now));
+			<CommitExpiries<T>>::mutate(expires_at, |accounts| accounts.push(sender.clone()));
+			<RevealTargets<T>>::mutate(now + T::MinRevealDelay::get(), |count| *count += 1);
+
+			Self::deposit_event(RawEvent::CreateCommitted(sender, commitment));
+		}
+
+		/// Reveal the salt of a previous `commit_create` and mint the kitty.
+		/// The DNA mixes the salt with the randomness of the block `MinRevealDelay` blocks after
+		/// the commitment, so it is the same whichever block the reveal comes in.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::REVEAL_CREATE)]
+		pub fn reveal_create(origin, salt: T::Hash) {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_active(Activity::Creation, &[&sender])?;
+
+			let (commitment, committed_at) = Self::create_commit(&sender).ok_or(Error::NoPendingCommit)?;
+
+			let now = <system::Module<T>>::block_number();
+			let target = committed_at + T::MinRevealDelay::get();
+			ensure!(now >= target, Error::RevealTooEarly);
+			ensure!(now < committed_at + T::CommitExpiry::get(), Error::CommitExpired);
+			ensure!(T::Hashing::hash_of(&(&sender, &salt)) == commitment, Error::InvalidReveal);
+			let randomness = Self::reveal_randomness(target).ok_or(Error::NoRevealRandomness)?;
+
+			let kitty_id = Self::next_kitty_id()?;
+
+			let dna = (salt, &sender, randomness).using_encoded(blake2_128);
+
+			Self::insert_kitty(&sender, kitty_id, Kitty(dna))?;
+			<CreateCommits<T>>::remove(&sender);
+			// A revealed commitment no longer takes a place in the expiry queue
+			<CommitExpiries<T>>::mutate(committed_at + T::CommitExpiry::get(), |accounts| accounts.retain(|a| *a != sender));
+			Self::release_reveal_target(target);
+
+			Self::deposit_event(RawEvent::Created(sender, kitty_id));
+		}
+
+		/// Offer to lend a kitty to `borrower` until the end of block `until`.
+		/// The rental starts once the borrower accepts it with `borrow` and pays `fee`.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::LEND)]
+		pub fn lend(origin, kitty_id: T::KittyIndex, borrower: T::AccountId, until: T::BlockNumber, fee: BalanceOf<T>) {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_active(Activity::Trading, &[&sender, &borrower])?;
+
+			ensure!(<OwnedKitties<T>>::exists(&(sender.clone(), Some(kitty_id))), Error::NotOwner);
+			ensure!(until > <system::Module<T>>::block_number(), Error::InvalidRentalPeriod);
+			ensure!(Self::rental_expiries(until).len() < MAX_EXPIRIES_PER_BLOCK, Error::ExpiryQueueFull);
+
+			Self::lock_kitty(kitty_id, LockReason::Rental)?;
+			<KittyRentals<T>>::insert(kitty_id, Rental {
+				borrower: borrower.clone(),
+				until,
+				fee,
+				active: false,
+			});
+			<RentalExpiries<T>>::mutate(until, |kitties| kitties.push(kitty_id));
+
+			Self::deposit_event(RawEvent::LendOffered(sender, borrower, kitty_id, until, fee));
+		}
+
+		/// Accept a rental offer, paying its fee to the owner.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::BORROW)]
+		pub fn borrow(origin, kitty_id: T::KittyIndex) {
+			let sender = ensure_signed(origin)?;
+
+			let mut rental = Self::kitty_rental(kitty_id).ok_or(Error::NoRental)?;
+			ensure!(rental.borrower == sender, Error::NotBorrower);
+			ensure!(!rental.active, Error::RentalAlreadyActive);
+			let owner = Self::kitty_owner(kitty_id).ok_or(Error::InvalidKittyId)?;
+			// A blocked owner can not be paid
+			Self::ensure_active(Activity::Trading, &[&sender, &owner])?;
+
+			T::Currency::transfer(&sender, &owner, rental.fee)?;
+
+			rental.active = true;
+			<KittyRentals<T>>::insert(kitty_id, rental);
+
+			Self::deposit_event(RawEvent::Borrowed(sender, kitty_id));
+		}
+
+		/// End a rental early. The borrower can return the kitty at any time, the owner can
+		/// only withdraw an offer that was not accepted yet. The fee is not refunded.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::RETURN_KITTY)]
+		pub fn return_kitty(origin, kitty_id: T::KittyIndex) {
+			let sender = ensure_signed(origin)?;
+
+			let rental = Self::kitty_rental(kitty_id).ok_or(Error::NoRental)?;
+			let owner = Self::kitty_owner(kitty_id).ok_or(Error::InvalidKittyId)?;
+			if sender == owner {
+				ensure!(!rental.active, Error::RentalAlreadyActive);
+			} else {
+				ensure!(rental.borrower == sender, Error::NotBorrower);
+			}
+
+			Self::end_rental(kitty_id);
+			Self::unlock_kitty(kitty_id, LockReason::Rental);
+
+			Self::deposit_event(RawEvent::Returned(owner, kitty_id));
+		}
+
+		/// Ask for a loan of `principal`, secured by a kitty. The loan has to be repaid with
+		/// `interest` within `duration` blocks of being funded, or the kitty goes to the lender.
+		/// The kitty is locked from now on.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::REQUEST_LOAN)]
+		pub fn request_loan(origin, kitty_id: T::KittyIndex, principal: BalanceOf<T>, interest: BalanceOf<T>, duration: T::BlockNumber) {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_active(Activity::Trading, &[&sender])?;
+
+			ensure!(<OwnedKitties<T>>::exists(&(sender.clone(), Some(kitty_id))), Error::NotOwner);
+			principal.checked_add(&interest).ok_or(Error::LoanOverflow)?;
+			ensure!(!duration.is_zero() && duration <= T::MaxLoanDuration::get(), Error::InvalidLoanDuration);
+
+			Self::lock_kitty(kitty_id, LockReason::Loan)?;
+			<KittyLoans<T>>::insert(kitty_id, Loan {
+				principal,
+				interest,
+				duration,
+				funded: None,
+			});
+
+			Self::deposit_event(RawEvent::LoanRequested(sender, kitty_id, principal, interest, duration));
+		}
+
+		/// Withdraw a loan request that was not funded yet, unlocking the kitty.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::CANCEL_LOAN_REQUEST)]
+		pub fn cancel_loan_request(origin, kitty_id: T::KittyIndex) {
+			let sender = ensure_signed(origin)?;
+
+			ensure!(<OwnedKitties<T>>::exists(&(sender.clone(), Some(kitty_id))), Error::NotOwner);
+			let loan = Self::kitty_loan(kitty_id).ok_or(Error::NoLoan)?;
+			ensure!(loan.funded.is_none(), Error::LoanAlreadyFunded);
+
+			<KittyLoans<T>>::remove(kitty_id);
+			Self::unlock_kitty(kitty_id, LockReason::Loan);
+
+			Self::deposit_event(RawEvent::LoanCancelled(sender, kitty_id));
+		}
+
+		/// Fund a loan request, sending the principal to the kitty owner. Fails unless the
+		/// request still asks for `principal`, `interest` and `duration`, so the owner can not
+		/// swap in other terms once the lender has sent this.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::FUND_LOAN)]
+		pub fn fund_loan(origin, kitty_id: T::KittyIndex, principal: BalanceOf<T>, interest: BalanceOf<T>, duration: T::BlockNumber) {
+			let sender = ensure_signed(origin)?;
+
+			let mut loan = Self::kitty_loan(kitty_id).ok_or(Error::NoLoan)?;
+			ensure!(loan.funded.is_none(), Error::LoanAlreadyFunded);
+			ensure!(
+				loan.principal == principal && loan.interest == interest && loan.duration == duration,
+				Error::LoanTermsChanged
+			);
+			let owner = Self::kitty_owner(kitty_id).ok_or(Error::InvalidKittyId)?;
+			// A blocked owner can not be paid
+			Self::ensure_active(Activity::Trading, &[&sender, &owner])?;
+			let deadline = <system::Module<T>>::block_number().checked_add(&loan.duration)
+				.ok_or(Error::LoanDeadlineOverflow)?;
+			ensure!(Self::loan_deadlines(deadline).len() < MAX_EXPIRIES_PER_BLOCK, Error::ExpiryQueueFull);
+
+			T::Currency::transfer(&sender, &owner, loan.principal)?;
+
+			loan.funded = Some((sender.clone(), deadline));
+			<KittyLoans<T>>::insert(kitty_id, loan);
+			<LoanDeadlines<T>>::mutate(deadline, |kitties| kitties.push(kitty_id));
+
+			Self::deposit_event(RawEvent::LoanFunded(sender, kitty_id, deadline));
+		}
+
+		/// Repay a funded loan with interest, unlocking the kitty.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::REPAY_LOAN)]
+		pub fn repay_loan(origin, kitty_id: T::KittyIndex) {
+			let sender = ensure_signed(origin)?;
+
+			ensure!(<OwnedKitties<T>>::exists(&(sender.clone(), Some(kitty_id))), Error::NotOwner);
+			let loan = Self::kitty_loan(kitty_id).ok_or(Error::NoLoan)?;
+			let (lender, _) = loan.funded.ok_or(Error::LoanNotFunded)?;
+			let amount = loan.principal.checked_add(&loan.interest).ok_or(Error::LoanOverflow)?;
+
+			T::Currency::transfer(&sender, &lender, amount)?;
+
+			Self::end_loan(kitty_id);
+			Self::unlock_kitty(kitty_id, LockReason::Loan);
+
+			Self::deposit_event(RawEvent::LoanRepaid(sender, lender, kitty_id));
+		}
+
+		/// Offer a kitty to `to`. It only changes hands once `to` accepts it with
+		/// `accept_transfer`, and stays locked until then.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::OFFER_TRANSFER)]
+		pub fn offer_transfer(origin, to: T::AccountId, kitty_id: T::KittyIndex) {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_active(Activity::Trading, &[&sender, &to])?;
+
+			ensure!(<OwnedKitties<T>>::exists(&(sender.clone(), Some(kitty_id))), Error::NotOwner);
+			let expires_at = <system::Module<T>>::block_number() + T::TransferOfferExpiry::get();
+			ensure!(Self::transfer_offer_expiries(expires_at).len() < MAX_EXPIRIES_PER_BLOCK, Error::ExpiryQueueFull);
+
+			Self::lock_kitty(kitty_id, LockReason::Offered)?;
+			<TransferOffers<T>>::insert(kitty_id, (to.clone(), expires_at));
+			<TransferOfferExpiries<T>>::mutate(expires_at, |kitties| kitties.push(kitty_id));
+
+			Self::deposit_event(RawEvent::TransferOffered(sender, to, kitty_id, expires_at));
+		}
+
+		/// Accept a kitty offered to the sender.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::ACCEPT_TRANSFER)]
+		pub fn accept_transfer(origin, kitty_id: T::KittyIndex) {
+			let sender = ensure_signed(origin)?;
+			Self::ensure_active(Activity::Trading, &[&sender])?;
+
+			let (recipient, _) = Self::transfer_offer(kitty_id).ok_or(Error::NoTransferOffer)?;
+			ensure!(recipient == sender, Error::NotRecipient);
+			let owner = Self::kitty_owner(kitty_id).ok_or(Error::InvalidKittyId)?;
+
+			// Check both lists before the offer is released
+			Self::ensure_can_relink(&owner, &sender, kitty_id)?;
+
+			Self::end_transfer_offer(kitty_id);
+			Self::unlock_kitty(kitty_id, LockReason::Offered);
+
+			Self::do_transfer(&owner, &sender, kitty_id)?;
+
+			Self::deposit_event(RawEvent::Transferred(owner, sender, kitty_id));
+		}
+
+		/// Withdraw a pending transfer offer.
+		#[weight = SimpleDispatchInfo::FixedNormal(weights::CANCEL_TRANSFER)]
+		pub fn cancel_transfer(origin, kitty_id: T::KittyIndex) {
+			let sender = ensure_signed(origin)?;
+
+			ensure!(<OwnedKitties<T>>::exists(&(sender.clone(), Some(kitty_id))), Error::NotOwner);
+			ensure!(<TransferOffers<T>>::exists(kitty_id), Error::NoTransferOffer);
+
+			Self::end_transfer_offer(kitty_id);
+			Self::unlock_kitty(kitty_id, LockReason::Offered);
+
+			Self::deposit_event(RawEvent::TransferOfferEnded(sender, kitty_id));
+		}
+
+		/// Pause or resume an activity. Calls that only unwind, like delisting, returning a
+		/// rented kitty or repaying a loan, stay available while paused.
+		#[weight = SimpleDispatchInfo::FixedOperational(weights::SET_PAUSED)]
+		pub fn set_paused(origin, activity: Activity, paused: bool) {
+			ensure_root(origin)?;
+
+			Paused::insert(activity, paused);
+
+			Self::deposit_event(RawEvent::PauseSet(activity, paused));
+		}
+
+		/// Move a kitty regardless of pauses, block lists and locks. Whatever holds the kitty
+		/// ends first: a listing is withdrawn, a rental or transfer offer ends without refunds,
+		/// and a loan or loan request is dropped, so a lender has to be settled with off chain.
+		#[weight = SimpleDispatchInfo::FixedOperational(weights::FORCE_TRANSFER)]
+		pub fn force_transfer(origin, from: T::AccountId, to: T::AccountId, kitty_id: T::KittyIndex) {
+			ensure_root(origin)?;
+
+			ensure!(<OwnedKitties<T>>::exists(&(from.clone(), Some(kitty_id))), Error::NotOwner);
+			// Check everything that can fail before the lock is released
+			Self::ensure_can_relink(&from, &to, kitty_id)?;
+			if Self::kitty_lock(kitty_id) == Some(LockReason::Listed) {
+				Self::ensure_listing_indexed(&from, kitty_id)?;
+			}
+
+			Self::force_unlock(&from, kitty_id)?;
+			Self::do_transfer(&from, &to, kitty_id)?;
+
+			Self::deposit_event(RawEvent::ForceTransferred(from, to, kitty_id));
+		}
+
+		/// Take a kitty off sale.
+		#[weight = SimpleDispatchInfo::FixedOperational(weights::FORCE_DELIST)]
+		pub fn force_delist(origin, kitty_id: T::KittyIndex) {
+			ensure_root(origin)?;
+
+			let owner = Self::kitty_owner(kitty_id).ok_or(Error::InvalidKittyId)?;
+			ensure!(Self::kitty_lock(kitty_id) == Some(LockReason::Listed), Error::NotForSale);
+
+			Self::unindex_listing(&owner, kitty_id)?;
+			Self::unlock_kitty(kitty_id, LockReason::Listed);
+			<KittyPrices<T>>::remove(kitty_id);
+			// Buyers holding the old listing must not match a later one
+			<ListingNonces<T>>::mutate(kitty_id, |nonce| *nonce = nonce.wrapping_add(1));
+
+			Self::deposit_event(RawEvent::ForceDelisted(owner, kitty_id));
+		}
+
+		/// Block an account from creating, breeding and trading kitties, or lift the block.
+		#[weight = SimpleDispatchInfo::FixedOperational(weights::SET_BLOCKED)]
+		pub fn set_blocked(origin, who: T::AccountId, blocked: bool) {
+			ensure_root(origin)?;
+
+			if blocked {
+				<BlockedAccounts<T>>::insert(&who, true);
+			} else {
+				<BlockedAccounts<T>>::remove(&who);
+			}
+
+			Self::deposit_event(RawEvent::BlockListUpdated(who, blocked));
+		}
+	}
+}
+
+decl_runtime_apis! {
+	/// API for inspecting the kitties storage from the node or an offchain worker.
+	#[api_version(3)]
+	pub trait KittiesApi<AccountId, KittyIndex, Balance> where
+		AccountId: Codec,
+		KittyIndex: Codec,
+		Balance: Codec,
+	{
+		/// Check that the kitties storage indexes agree with each other.
+		/// Returns the message of the first inconsistency found. The check is only compiled
+		/// into the native runtime, the wasm runtime always returns an error.
+		fn check_integrity() -> result::Result<(), Vec<u8>>;
+		/// A page of the kitties for sale, see `Module::listings_page`.
+		fn listings(seller: Option<AccountId>, after: Option<KittyIndex>, limit: u32) -> Vec<(KittyIndex, Balance)>;
+		/// The number of kitties ever created, which is also the next kitty index.
+		fn kitties_count() -> KittyIndex;
+		/// A kitty with its owner, price and lock, see `Module::kitty_details`.
+		fn kitty(kitty_id: KittyIndex) -> Option<KittyDetails<AccountId, Balance>>;
+		/// A page of the kitties of an owner, see `Module::owned_page`.
+		fn owned(owner: AccountId, after: Option<KittyIndex>, limit: u32) -> Vec<KittyIndex>;
+	}
+}
+
+impl<T: Trait> KittyPriceOracle<T::KittyIndex, BalanceOf<T>> for Module<T> {
+	fn reference_price(kitty_id: T::KittyIndex) -> Option<BalanceOf<T>> {
+		Self::sale_history(kitty_id).last().map(|sale| sale.price)
+			.or_else(|| Self::kitty(kitty_id).and_then(|kitty| Self::average_sale_price(kitty.rarity_tier())))
 	}
 }
 
@@ -140,41 +1120,62 @@ fn combine_dna(dna1: u8, dna2: u8, selector: u8) -> u8 {
 }
 
 impl<T: Trait> Module<T> {
+	/// The account currently holding a kitty: the borrower during an active rental,
+	/// otherwise the owner.
+	pub fn kitty_holder(kitty_id: T::KittyIndex) -> Option<T::AccountId> {
+		match Self::kitty_rental(kitty_id) {
+			Some(Rental { borrower, active: true, .. }) => Some(borrower),
+			_ => Self::kitty_owner(kitty_id),
+		}
+	}
+
+	/// Random bytes for a kitty created or bred by `sender` in the current extrinsic.
+	///
+	/// The value can only be as unpredictable as the block's input to `T::Randomness`, which
+	/// the block author knows before anyone else. Senders who must not be ground against
+	/// should mint with `commit_create` and `reveal_create` instead.
 	fn random_value(sender: &T::AccountId) -> [u8; 16] {
-		let payload = (<system::Module<T>>::random_seed(), sender, <system::Module<T>>::extrinsic_index(), <system::Module<T>>::block_number());
-		payload.using_encoded(blake2_128)
+		let subject = (sender, <system::Module<T>>::extrinsic_index(), <system::Module<T>>::block_number());
+		subject.using_encoded(T::Randomness::random)
 	}
 
-	fn next_kitty_id() -> result::Result<T::KittyIndex, &'static str> {
+	fn next_kitty_id() -> result::Result<T::KittyIndex, Error> {
 		let kitty_id = Self::kitties_count();
 		if kitty_id == T::KittyIndex::max_value() {
-			return Err("Kitties count overflow");
+			return Err(Error::KittiesCountOverflow);
 		}
 		Ok(kitty_id)
 	}
 
-	fn insert_owned_kitty(owner: &T::AccountId, kitty_id: T::KittyIndex) {
-		<OwnedKittiesList<T>>::append(owner, kitty_id);
+	fn insert_owned_kitty(owner: &T::AccountId, kitty_id: T::KittyIndex) -> result::Result<(), Error> {
+		<OwnedKittiesList<T>>::append(owner, kitty_id)?;
+		Ok(())
 	}
 
-	fn insert_kitty(owner: &T::AccountId, kitty_id: T::KittyIndex, kitty: Kitty) {
+	fn insert_kitty(owner: &T::AccountId, kitty_id: T::KittyIndex, kitty: Kitty) -> result::Result<(), Error> {
+		// Link the kitty first, this fails without writing anything if it is already owned
+		Self::insert_owned_kitty(owner, kitty_id)?;
+
 		// Create and store kitty
 		<Kitties<T>>::insert(kitty_id, kitty);
 		<KittiesCount<T>>::put(kitty_id + 1.into());
 		<KittyOwners<T>>::insert(kitty_id, owner.clone());
 
-		Self::insert_owned_kitty(owner, kitty_id);
+		Ok(())
 	}
 
-	fn do_breed(sender: &T::AccountId, kitty_id_1: T::KittyIndex, kitty_id_2: T::KittyIndex) -> result::Result<T::KittyIndex, &'static str> {
+	fn do_breed(sender: &T::AccountId, kitty_id_1: T::KittyIndex, kitty_id_2: T::KittyIndex) -> result::Result<T::KittyIndex, Error> {
 		let kitty1 = Self::kitty(kitty_id_1);
 		let kitty2 = Self::kitty(kitty_id_2);
 
-		ensure!(kitty1.is_some(), "Invalid kitty_id_1");
-		ensure!(kitty2.is_some(), "Invalid kitty_id_2");
-		ensure!(kitty_id_1 != kitty_id_2, "Needs different parent");
-		ensure!(Self::kitty_owner(&kitty_id_1).map(|owner| owner == *sender).unwrap_or(false), "Not onwer of kitty1");
- 		ensure!(Self::kitty_owner(&kitty_id_2).map(|owner| owner == *sender).unwrap_or(false), "Not owner of kitty2");
+		ensure!(kitty1.is_some(), Error::InvalidKittyId);
+		ensure!(kitty2.is_some(), Error::InvalidKittyId);
+		ensure!(kitty_id_1 != kitty_id_2, Error::RequireDifferentParent);
+		Self::ensure_can_breed(kitty_id_1)?;
+		Self::ensure_can_breed(kitty_id_2)?;
+		// Borrowers may breed with the kitties they hold
+		ensure!(Self::kitty_holder(kitty_id_1).map(|holder| holder == *sender).unwrap_or(false), Error::NotOwner);
+		ensure!(Self::kitty_holder(kitty_id_2).map(|holder| holder == *sender).unwrap_or(false), Error::NotOwner);
 
 		let kitty_id = Self::next_kitty_id()?;
 
@@ -190,91 +1191,576 @@ impl<T: Trait> Module<T> {
 			new_dna[i] = combine_dna(kitty1_dna[i], kitty2_dna[i], selector[i]);
 		}
 
-		Self::insert_kitty(sender, kitty_id, Kitty(new_dna));
+		Self::insert_kitty(sender, kitty_id, Kitty(new_dna))?;
 
 		Ok(kitty_id)
 	}
 
-	fn do_transfer(from: &T::AccountId, to: &T::AccountId, kitty_id: T::KittyIndex)  {
- 		<OwnedKittiesList<T>>::remove(&from, kitty_id);
- 		<OwnedKittiesList<T>>::append(&to, kitty_id);
- 		<KittyOwners<T>>::insert(kitty_id, to);
- 	}
-}
+	/// Add a sale to the kitty's history and its tier's rolling average.
+	fn record_sale(kitty_id: T::KittyIndex, sale: SaleOf<T>) {
+		if let Some(kitty) = Self::kitty(kitty_id) {
+			let window = BalanceOf::<T>::from(SALE_AVERAGE_WINDOW);
+			<AverageSalePrice<T>>::mutate(kitty.rarity_tier(), |average| {
+				*average = Some(match average.take() {
+					// Divide before adding so that large prices can not overflow
+					Some(average) => average - average / window + sale.price / window,
+					None => sale.price,
+				});
+			});
+		}
 
-/// Tests for Kitties module
-#[cfg(test)]
-mod tests {
-	use super::*;
+		<SaleHistory<T>>::mutate(kitty_id, |history| {
+			if history.len() >= MAX_SALE_HISTORY {
+				history.remove(0);
+			}
+			history.push(sale);
+		});
+	}
 
-	use runtime_io::with_externalities;
-	use primitives::{H256, Blake2Hasher};
-	use support::{impl_outer_origin, parameter_types};
-	use sr_primitives::{traits::{BlakeTwo256, IdentityLookup}, testing::Header};
-	use sr_primitives::weights::Weight;
-	use sr_primitives::Perbill;
+	/// Remove the commitments that expire at the end of block `n`.
+	fn expire_commits(n: T::BlockNumber) {
+		for account in <CommitExpiries<T>>::take(n) {
+			// The commitment may have been revealed and replaced by a newer one since
+			let committed_at = match Self::create_commit(&account) {
+				Some((_, committed_at)) if committed_at + T::CommitExpiry::get() == n => committed_at,
+				_ => continue,
+			};
+			<CreateCommits<T>>::remove(&account);
+			Self::release_reveal_target(committed_at + T::MinRevealDelay::get());
+			Self::deposit_event(RawEvent::CreateCommitExpired(account));
+		}
+	}
 
-	impl_outer_origin! {
-		pub enum Origin for Test {}
+	/// Drop a revealed or expired commitment's claim on the randomness of block `target`,
+	/// removing the randomness once no pending commitment needs it.
+	fn release_reveal_target(target: T::BlockNumber) {
+		let remaining = Self::reveal_targets(target).saturating_sub(1);
+		if remaining == 0 {
+			<RevealTargets<T>>::remove(target);
+			<RevealRandomness<T>>::remove(target);
+		} else {
+			<RevealTargets<T>>::insert(target, remaining);
+		}
 	}
 
-	// For testing the module, we construct most of a mock runtime. This means
-	// first constructing a configuration type (`Test`) which `impl`s each of the
-	// configuration traits of modules we want to use.
-	#[derive(Clone, Eq, PartialEq, Debug)]
-	pub struct Test;
-	parameter_types! {
-		pub const BlockHashCount: u64 = 250;
-		pub const MaximumBlockWeight: Weight = 1024;
-		pub const MaximumBlockLength: u32 = 2 * 1024;
-		pub const AvailableBlockRatio: Perbill = Perbill::from_percent(75);
+	/// Return the kitties whose rental ends at the end of block `n` to their owners.
+	fn expire_rentals(n: T::BlockNumber) {
+		for kitty_id in <RentalExpiries<T>>::take(n) {
+			// The rental may have been ended early and replaced by a new one since
+			let expired = Self::kitty_rental(kitty_id).map(|rental| rental.until == n).unwrap_or(false);
+			if expired {
+				<KittyRentals<T>>::remove(kitty_id);
+				Self::unlock_kitty(kitty_id, LockReason::Rental);
+				if let Some(owner) = Self::kitty_owner(kitty_id) {
+					Self::deposit_event(RawEvent::Returned(owner, kitty_id));
+				}
+			}
+		}
 	}
-	impl system::Trait for Test {
-		type Origin = Origin;
-		type Call = ();
-		type Index = u64;
-		type BlockNumber = u64;
-		type Hash = H256;
-		type Hashing = BlakeTwo256;
-		type AccountId = u64;
-		type Lookup = IdentityLookup<Self::AccountId>;
-		type Header = Header;
-		type WeightMultiplierUpdate = ();
-		type Event = ();
-		type BlockHashCount = BlockHashCount;
-		type MaximumBlockWeight = MaximumBlockWeight;
-		type MaximumBlockLength = MaximumBlockLength;
-		type AvailableBlockRatio = AvailableBlockRatio;
-		type Version = ();
+
+	/// Withdraw the transfer offers that expire at the end of block `n`.
+	fn expire_transfer_offers(n: T::BlockNumber) {
+		for kitty_id in <TransferOfferExpiries<T>>::take(n) {
+			// The offer may have been accepted or cancelled, and a new one made since
+			let expired = Self::transfer_offer(kitty_id).map(|(_, expires_at)| expires_at == n).unwrap_or(false);
+			if expired {
+				<TransferOffers<T>>::remove(kitty_id);
+				Self::unlock_kitty(kitty_id, LockReason::Offered);
+				if let Some(owner) = Self::kitty_owner(kitty_id) {
+					Self::deposit_event(RawEvent::TransferOfferEnded(owner, kitty_id));
+				}
+			}
+		}
 	}
-	parameter_types! {
-		pub const ExistentialDeposit: u64 = 0;
-		pub const TransferFee: u64 = 0;
-		pub const CreationFee: u64 = 0;
-		pub const TransactionBaseFee: u64 = 0;
-		pub const TransactionByteFee: u64 = 0;
+
+	/// Hand the collateral of loans due at the end of block `n` to their lenders.
+	fn seize_overdue_collateral(n: T::BlockNumber) {
+		for kitty_id in <LoanDeadlines<T>>::take(n) {
+			// The loan may have been repaid, and a new one taken out since
+			let lender = match Self::kitty_loan(kitty_id).and_then(|loan| loan.funded) {
+				Some((lender, deadline)) if deadline == n => lender,
+				_ => continue,
+			};
+			let owner = match Self::kitty_owner(kitty_id) {
+				Some(owner) => owner,
+				None => continue,
+			};
+
+			<KittyLoans<T>>::remove(kitty_id);
+			Self::unlock_kitty(kitty_id, LockReason::Loan);
+			// Nothing else can lock a pledged kitty, so this only fails on corrupted lists
+			if Self::do_transfer(&owner, &lender, kitty_id).is_ok() {
+				Self::deposit_event(RawEvent::CollateralSeized(owner, lender, kitty_id));
+			}
+		}
 	}
-	impl balances::Trait for Test {
-		type Balance = u64;
-		type OnFreeBalanceZero = ();
-		type OnNewAccount = ();
-		type Event = ();
-		type TransactionPayment = ();
-		type TransferPayment = ();
-		type DustRemoval = ();
-		type ExistentialDeposit = ExistentialDeposit;
+
+	/// Migrate up to `MIGRATION_BATCH` kitties towards the next storage version, continuing
+	/// where the previous block stopped. Hooks can not report their weight to the system module,
+	/// so the weight of the batch goes into the `StorageMigrated` event.
+	fn migrate_batch() {
+		let version = Self::storage_version();
+		let cursor = Self::migration_progress();
+		let (migrated, next) = match version {
+			0 => Self::migrate_to_v1(cursor),
+			1 => Self::migrate_to_v2(cursor),
+			_ => Self::migrate_to_v3(cursor),
+		};
+
+		match next {
+			Some(cursor) => <MigrationProgress<T>>::put(cursor),
+			None => {
+				<MigrationProgress<T>>::kill();
+				StorageVersion::put(version + 1);
+			}
+		}
+
+		Self::deposit_event(RawEvent::StorageMigrated(version + 1, migrated, weights::migration_batch(migrated)));
+	}
+
+	/// Rebuild `KittyOwners` by walking the owned list of every known account.
+	/// Returns the number of kitties visited and the cursor to continue from, None once done.
+	fn migrate_to_v1(cursor: Option<MigrationCursor<T::KittyIndex>>) -> (u32, Option<MigrationCursor<T::KittyIndex>>) {
+		if Self::kitties_count() == T::KittyIndex::default() {
+			return (0, None);
+		}
+
+		let (mut batch, mut position, mut after) = match cursor {
+			Some(MigrationCursor::Accounts { batch, account, after }) => (batch, account, after),
+			_ => (0, 0, None),
+		};
+		let mut migrated = 0;
+		loop {
+			let accounts = T::KnownAccounts::known_accounts_batch(batch);
+			if accounts.is_empty() {
+				return (migrated, None);
+			}
+
+			while let Some(account) = accounts.get(position as usize) {
+				let mut current = Self::owned_kitties(&(account.clone(), after)).and_then(|item| item.next);
+				while let Some(kitty_id) = current {
+					if migrated == MIGRATION_BATCH {
+						return (migrated, Some(MigrationCursor::Accounts { batch, account: position, after }));
+					}
+					// A kitty that already has this owner was visited before, so the list loops
+					if <KittyOwners<T>>::get(kitty_id).as_ref() == Some(account) {
+						break;
+					}
+					if <Kitties<T>>::exists(kitty_id) && !<KittyOwners<T>>::exists(kitty_id) {
+						<KittyOwners<T>>::insert(kitty_id, account);
+					}
+					migrated += 1;
+					after = Some(kitty_id);
+					current = Self::owned_kitties(&(account.clone(), Some(kitty_id))).and_then(|item| item.next);
+				}
+				position += 1;
+				after = None;
+			}
+			batch += 1;
+			position = 0;
+		}
+	}
+
+	/// Lock the listed, rented out and pledged kitties written before `KittyLocks` existed.
+	/// Returns the number of kitties visited and the cursor to continue from, None once done.
+	fn migrate_to_v2(cursor: Option<MigrationCursor<T::KittyIndex>>) -> (u32, Option<MigrationCursor<T::KittyIndex>>) {
+		Self::migrate_kitties(cursor, |kitty_id| {
+			let reason = if <KittyLoans<T>>::exists(kitty_id) {
+				Some(LockReason::Loan)
+			} else if <KittyRentals<T>>::exists(kitty_id) {
+				Some(LockReason::Rental)
+			} else if <KittyPrices<T>>::exists(kitty_id) {
+				Some(LockReason::Listed)
+			} else {
+				None
+			};
+			if let Some(reason) = reason {
+				<KittyLocks<T>>::insert(kitty_id, reason);
+			}
+		})
+	}
+
+	/// Add the kitties for sale before `Listings` existed to the listings.
+	/// Returns the number of kitties visited and the cursor to continue from, None once done.
+	fn migrate_to_v3(cursor: Option<MigrationCursor<T::KittyIndex>>) -> (u32, Option<MigrationCursor<T::KittyIndex>>) {
+		Self::migrate_kitties(cursor, |kitty_id| {
+			if <KittyPrices<T>>::exists(kitty_id) {
+				if let Some(owner) = Self::kitty_owner(kitty_id) {
+					// Only fails if the kitty is already listed
+					let _ = Self::index_listing(&owner, kitty_id);
+				}
+			}
+		})
+	}
+
+	/// Run `migrate` on up to `MIGRATION_BATCH` kitties, from the one at `cursor` on.
+	fn migrate_kitties(
+		cursor: Option<MigrationCursor<T::KittyIndex>>,
+		migrate: impl Fn(T::KittyIndex),
+	) -> (u32, Option<MigrationCursor<T::KittyIndex>>) {
+		let count = Self::kitties_count();
+		let mut kitty_id = match cursor {
+			Some(MigrationCursor::Kitties(kitty_id)) => kitty_id,
+			_ => T::KittyIndex::default(),
+		};
+		let mut migrated = 0;
+		while kitty_id < count {
+			if migrated == MIGRATION_BATCH {
+				return (migrated, Some(MigrationCursor::Kitties(kitty_id)));
+			}
+			migrate(kitty_id);
+			migrated += 1;
+			kitty_id = kitty_id + 1.into();
+		}
+		(migrated, None)
+	}
+
+	/// Up to `limit` kitties for sale with their prices, following `after` in listing order,
+	/// or from the start when `after` is None. Lists every seller when `seller` is None.
+	pub fn listings_page(
+		seller: Option<T::AccountId>,
+		after: Option<T::KittyIndex>,
+		limit: u32,
+	) -> Vec<(T::KittyIndex, BalanceOf<T>)> {
+		<ListingsList<T>>::page(&seller, after, limit.min(MAX_LISTINGS_PAGE) as usize)
+			.into_iter()
+			.filter_map(|kitty_id| Self::kitty_price(kitty_id).map(|price| (kitty_id, price)))
+			.collect()
+	}
+
+	/// A kitty with its owner, asking price and lock. None if the kitty does not exist.
+	pub fn kitty_details(kitty_id: T::KittyIndex) -> Option<KittyDetails<T::AccountId, BalanceOf<T>>> {
+		let kitty = Self::kitty(kitty_id)?;
+		let owner = Self::kitty_owner(kitty_id)?;
+		Some(KittyDetails {
+			dna: kitty.0,
+			owner,
+			price: Self::kitty_price(kitty_id),
+			lock: Self::kitty_lock(kitty_id),
+		})
+	}
+
+	/// Up to `limit` kitties of `owner`, following `after` in the order they were acquired,
+	/// or from the start when `after` is None.
+	pub fn owned_page(
+		owner: T::AccountId,
+		after: Option<T::KittyIndex>,
+		limit: u32,
+	) -> Vec<T::KittyIndex> {
+		<OwnedKittiesList<T>>::page(&owner, after, limit.min(MAX_OWNED_PAGE) as usize)
+	}
+
+	/// Check that `Kitties`, `KittyOwners`, `OwnedKitties`, `KittyPrices`, `KittyLocks`, `Listings`
+	/// and `KittiesCount` are consistent with each other. This walks every owner's list, so it is only meant
+	/// for tests and debugging, and is left out of the wasm runtime.
+	///
+	/// Maps keyed by kitty can not be enumerated, so entries for ids above `KittiesCount` are
+	/// only found in the owned and listing lists, which are walked, or at `KittiesCount`
+	/// itself. A stray price or lock further up that no list links to goes unnoticed.
+	#[cfg(feature = "std")]
+	pub fn check_integrity() -> result::Result<(), &'static str> {
+		let count = Self::kitties_count();
+		ensure!(!<Kitties<T>>::exists(count), "Kitty exists at KittiesCount");
+		ensure!(!<KittyOwners<T>>::exists(count), "Owner recorded at KittiesCount");
+		ensure!(!<KittyPrices<T>>::exists(count), "Price recorded at KittiesCount");
+		ensure!(!<KittyRentals<T>>::exists(count), "Rental recorded at KittiesCount");
+		ensure!(!<KittyLoans<T>>::exists(count), "Loan recorded at KittiesCount");
+		ensure!(!<KittyLocks<T>>::exists(count), "Lock recorded at KittiesCount");
+		ensure!(!<TransferOffers<T>>::exists(count), "Transfer offer recorded at KittiesCount");
+		ensure!(!<SaleHistory<T>>::exists(count), "Sale history recorded at KittiesCount");
+
+		let mut owners = BTreeSet::new();
+		let mut kitty_id = T::KittyIndex::default();
+		while kitty_id < count {
+			ensure!(<Kitties<T>>::exists(kitty_id), "Kitty missing below KittiesCount");
+			let owner = Self::kitty_owner(kitty_id).ok_or("Kitty has no owner")?;
+			ensure!(<OwnedKittiesList<T>>::contains(&owner, kitty_id), "Kitty missing from owner's list");
+			let lock = Self::kitty_lock(kitty_id);
+			let priced = <KittyPrices<T>>::exists(kitty_id);
+			ensure!(priced == (lock == Some(LockReason::Listed)), "Price and listed lock disagree");
+			ensure!(priced == <ListingsList<T>>::contains(&None, kitty_id), "Price and listings disagree");
+			ensure!(priced == <ListingsList<T>>::contains(&Some(owner.clone()), kitty_id), "Price and seller's listings disagree");
+			ensure!(<KittyRentals<T>>::exists(kitty_id) == (lock == Some(LockReason::Rental)), "Rental and rental lock disagree");
+			ensure!(<KittyLoans<T>>::exists(kitty_id) == (lock == Some(LockReason::Loan)), "Loan and loan lock disagree");
+			ensure!(<TransferOffers<T>>::exists(kitty_id) == (lock == Some(LockReason::Offered)), "Transfer offer and offered lock disagree");
+			owners.insert(owner);
+			kitty_id = kitty_id + 1.into();
+		}
+
+		// Every kitty is known to be in its owner's list, so walking each owner's list once
+		// catches duplicates, foreign entries and broken links. The lists of known accounts that
+		// own no kitty are walked too, so entries left behind in them are caught as well.
+		let others: Vec<_> = T::KnownAccounts::known_accounts().into_iter()
+			.filter(|account| !owners.contains(account) && <OwnedKitties<T>>::exists(&(account.clone(), None)))
+			.collect();
+		let accounts: Vec<_> = owners.into_iter().chain(others).collect();
+		let mut seen = BTreeSet::new();
+		for owner in &accounts {
+			let owner = owner.clone();
+			let head = Self::owned_kitties(&(owner.clone(), None)).ok_or("Owner's list has no head")?;
+			let mut prev = None;
+			let mut current = head.next;
+			while let Some(kitty_id) = current {
+				ensure!(seen.insert(kitty_id), "Kitty appears more than once in owned lists");
+				ensure!(Self::kitty_owner(kitty_id).as_ref() == Some(&owner), "Owned list contains a kitty of another owner");
+
+				let item = Self::owned_kitties(&(owner.clone(), Some(kitty_id))).ok_or("Owned list links to a missing item")?;
+				ensure!(item.prev == prev, "Owned list links are not symmetric");
+
+				prev = current;
+				current = item.next;
+			}
+			ensure!(head.prev == prev, "Owned list head does not point to the last item");
+		}
+
+		// Every kitty for sale is known to be listed, so only entries for unknown kitties or
+		// kitties without a price are left. Each id is checked against `KittiesCount`, since
+		// `KittyPrices` above it can not be enumerated.
+		let walk_listings = |seller: Option<T::AccountId>| -> result::Result<(), &'static str> {
+			let mut listed = BTreeSet::new();
+			let mut current = Self::listings(&(seller.clone(), None)).and_then(|head| head.next);
+			while let Some(kitty_id) = current {
+				ensure!(listed.insert(kitty_id), "Kitty appears more than once in the listings");
+				ensure!(kitty_id < count, "Listing for a kitty at or above KittiesCount");
+				ensure!(<KittyPrices<T>>::exists(kitty_id), "Listed kitty has no price");
+				if let Some(ref seller) = seller {
+					ensure!(Self::kitty_owner(kitty_id).as_ref() == Some(seller), "Seller's listings contain a kitty of another owner");
+				}
+				current = Self::listings(&(seller.clone(), Some(kitty_id))).and_then(|item| item.next);
+			}
+			Ok(())
+		};
+		walk_listings(None)?;
+		for seller in accounts {
+			walk_listings(Some(seller))?;
+		}
+
+		Ok(())
+	}
+
+	/// Fails if `activity` is paused or any of `accounts` is blocked.
+	fn ensure_active(activity: Activity, accounts: &[&T::AccountId]) -> result::Result<(), Error> {
+		ensure!(!Self::is_paused(activity), Error::ActivityPaused);
+		for account in accounts {
+			ensure!(!Self::is_blocked(*account), Error::AccountBlocked);
+		}
+		Ok(())
+	}
+
+	/// Fails with the error matching the kitty's lock, if it has one.
+	fn ensure_unlocked(kitty_id: T::KittyIndex) -> result::Result<(), Error> {
+		match Self::kitty_lock(kitty_id) {
+			Some(reason) => Err(reason.into()),
+			None => Ok(()),
+		}
+	}
+
+	fn ensure_can_breed(kitty_id: T::KittyIndex) -> result::Result<(), Error> {
+		match Self::kitty_lock(kitty_id) {
+			Some(reason) if !reason.allows_breeding() => Err(reason.into()),
+			_ => Ok(()),
+		}
+	}
+
+	/// End whatever holds a kitty owned by `owner` and release its lock.
+	fn force_unlock(owner: &T::AccountId, kitty_id: T::KittyIndex) -> result::Result<(), Error> {
+		match Self::kitty_lock(kitty_id) {
+			Some(LockReason::Listed) => {
+				Self::unindex_listing(owner, kitty_id)?;
+				<KittyPrices<T>>::remove(kitty_id);
+				<ListingNonces<T>>::mutate(kitty_id, |nonce| *nonce = nonce.wrapping_add(1));
+			}
+			Some(LockReason::Rental) => Self::end_rental(kitty_id),
+			Some(LockReason::Loan) => Self::end_loan(kitty_id),
+			Some(LockReason::Offered) => Self::end_transfer_offer(kitty_id),
+			Some(LockReason::Auction) | Some(LockReason::Pregnant) | None => {}
+		}
+		<KittyLocks<T>>::remove(kitty_id);
+		Ok(())
+	}
+
+	/// Remove a rental before it expires, taking it off its expiry queue so that it no longer
+	/// counts towards `MAX_EXPIRIES_PER_BLOCK`.
+	fn end_rental(kitty_id: T::KittyIndex) {
+		if let Some(rental) = <KittyRentals<T>>::take(kitty_id) {
+			<RentalExpiries<T>>::mutate(rental.until, |kitties| kitties.retain(|id| *id != kitty_id));
+		}
+	}
+
+	/// Remove a loan before it is due, taking a funded one off its deadline queue.
+	fn end_loan(kitty_id: T::KittyIndex) {
+		if let Some((_, deadline)) = <KittyLoans<T>>::take(kitty_id).and_then(|loan| loan.funded) {
+			<LoanDeadlines<T>>::mutate(deadline, |kitties| kitties.retain(|id| *id != kitty_id));
+		}
+	}
+
+	/// Remove a transfer offer before it expires, taking it off its expiry queue.
+	fn end_transfer_offer(kitty_id: T::KittyIndex) {
+		if let Some((_, expires_at)) = <TransferOffers<T>>::take(kitty_id) {
+			<TransferOfferExpiries<T>>::mutate(expires_at, |kitties| kitties.retain(|id| *id != kitty_id));
+		}
+	}
+
+	/// Lock an unlocked kitty for `reason`.
+	fn lock_kitty(kitty_id: T::KittyIndex, reason: LockReason) -> result::Result<(), Error> {
+		Self::ensure_unlocked(kitty_id)?;
+		<KittyLocks<T>>::insert(kitty_id, reason);
+		Ok(())
+	}
+
+	/// Release the kitty's lock if it is held for `reason`.
+	fn unlock_kitty(kitty_id: T::KittyIndex, reason: LockReason) {
+		if Self::kitty_lock(kitty_id) == Some(reason) {
+			<KittyLocks<T>>::remove(kitty_id);
+		}
+	}
+
+	fn ensure_listing_indexed(seller: &T::AccountId, kitty_id: T::KittyIndex) -> result::Result<(), Error> {
+		ensure!(<ListingsList<T>>::contains(&None, kitty_id), Error::KittyNotInList);
+		ensure!(<ListingsList<T>>::contains(&Some(seller.clone()), kitty_id), Error::KittyNotInList);
+		Ok(())
+	}
+
+	/// Add a kitty to the global and the seller's listings.
+	fn index_listing(seller: &T::AccountId, kitty_id: T::KittyIndex) -> result::Result<(), Error> {
+		// Check both lists up front so that a failing append never follows a written one
+		ensure!(!<ListingsList<T>>::contains(&None, kitty_id), Error::KittyAlreadyInList);
+		ensure!(!<ListingsList<T>>::contains(&Some(seller.clone()), kitty_id), Error::KittyAlreadyInList);
+		<ListingsList<T>>::append(&None, kitty_id)?;
+		<ListingsList<T>>::append(&Some(seller.clone()), kitty_id)?;
+		Ok(())
+	}
+
+	/// Remove a kitty from the global and the seller's listings.
+	fn unindex_listing(seller: &T::AccountId, kitty_id: T::KittyIndex) -> result::Result<(), Error> {
+		Self::ensure_listing_indexed(seller, kitty_id)?;
+		<ListingsList<T>>::remove(&None, kitty_id)?;
+		<ListingsList<T>>::remove(&Some(seller.clone()), kitty_id)?;
+		Ok(())
+	}
+
+	fn ensure_can_relink(from: &T::AccountId, to: &T::AccountId, kitty_id: T::KittyIndex) -> result::Result<(), Error> {
+		ensure!(<OwnedKittiesList<T>>::contains(from, kitty_id), Error::KittyNotInList);
+		ensure!(from == to || !<OwnedKittiesList<T>>::contains(to, kitty_id), Error::KittyAlreadyInList);
+		Ok(())
+	}
+
+	/// Move a kitty to a new owner. Every ownership change goes through here, so a locked
+	/// kitty can never move.
+	fn do_transfer(from: &T::AccountId, to: &T::AccountId, kitty_id: T::KittyIndex) -> result::Result<(), Error> {
+		Self::ensure_unlocked(kitty_id)?;
+		// Check both lists up front so that a failing append never follows a written remove
+		Self::ensure_can_relink(from, to, kitty_id)?;
+
+		<OwnedKittiesList<T>>::remove(&from, kitty_id)?;
+		<OwnedKittiesList<T>>::append(&to, kitty_id)?;
+		<KittyOwners<T>>::insert(kitty_id, to);
+
+		Ok(())
+	}
+}
+
+/// Tests for Kitties module
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use runtime_io::with_externalities;
+	use primitives::{H256, Blake2Hasher};
+	use support::{impl_outer_origin, assert_ok, assert_noop, parameter_types};
+	use sr_primitives::{traits::{BlakeTwo256, IdentityLookup, OnInitialize, OnFinalize, Dispatchable}, testing::Header};
+	use sr_primitives::weights::{Weight, GetDispatchInfo};
+	use sr_primitives::Perbill;
+	use runtime_io::twox_128;
+	use std::collections::BTreeMap;
+	use std::time::{Duration, Instant};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	// For testing the module, we construct most of a mock runtime. This means
+	// first constructing a configuration type (`Test`) which `impl`s each of the
+	// configuration traits of modules we want to use.
+	#[derive(Clone, Eq, PartialEq, Debug)]
+	pub struct Test;
+	parameter_types! {
+		pub const BlockHashCount: u64 = 250;
+		// The runtime's, so that the heaviest call and a full `on_finalize` fit in a block
+		pub const MaximumBlockWeight: Weight = 1_000_000;
+		pub const MaximumBlockLength: u32 = 2 * 1024;
+		pub const AvailableBlockRatio: Perbill = Perbill::from_percent(75);
+	}
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Call = ();
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type WeightMultiplierUpdate = ();
+		type Event = ();
+		type BlockHashCount = BlockHashCount;
+		type MaximumBlockWeight = MaximumBlockWeight;
+		type MaximumBlockLength = MaximumBlockLength;
+		type AvailableBlockRatio = AvailableBlockRatio;
+		type Version = ();
+	}
+	parameter_types! {
+		pub const ExistentialDeposit: u64 = 0;
+		pub const TransferFee: u64 = 0;
+		pub const CreationFee: u64 = 0;
+		pub const TransactionBaseFee: u64 = 0;
+		pub const TransactionByteFee: u64 = 0;
+	}
+	impl balances::Trait for Test {
+		type Balance = u64;
+		type OnFreeBalanceZero = ();
+		type OnNewAccount = ();
+		type Event = ();
+		type TransactionPayment = ();
+		type TransferPayment = ();
+		type DustRemoval = ();
+		type ExistentialDeposit = ExistentialDeposit;
 		type TransferFee = TransferFee;
 		type CreationFee = CreationFee;
 		type TransactionBaseFee = TransactionBaseFee;
 		type TransactionByteFee = TransactionByteFee;
 		type WeightToFee = ();
 	}
+	parameter_types! {
+		pub const MinRevealDelay: u64 = 2;
+		pub const CommitExpiry: u64 = 10;
+		pub const TransferOfferExpiry: u64 = 5;
+		pub const MaxLoanDuration: u64 = 100;
+	}
 	impl Trait for Test {
 		type KittyIndex = u32;
 		type Currency = balances::Module<Test>;
 		type Event = ();
+		type KnownAccounts = TestAccounts;
+		type Randomness = ();
+		type MinRevealDelay = MinRevealDelay;
+		type CommitExpiry = CommitExpiry;
+		type TransferOfferExpiry = TransferOfferExpiry;
+		type MaxLoanDuration = MaxLoanDuration;
+	}
+
+	pub struct TestAccounts;
+	impl KnownAccounts<u64> for TestAccounts {
+		fn known_accounts_batch(batch: u32) -> Vec<u64> {
+			match batch {
+				0 => vec![1, 2],
+				1 => vec![3],
+				_ => Vec::new(),
+			}
+		}
 	}
 	type OwnedKittiesTest = OwnedKitties<Test>;
+	type KittyModule = Module<Test>;
 
 	// This function basically just builds a genesis storage key/value store according to
 	// our desired mockup.
@@ -282,10 +1768,20 @@ mod tests {
 		system::GenesisConfig::default().build_storage::<Test>().unwrap().into()
 	}
 
+	// Runs a test against fresh storage and checks the kitties indexes are still
+	// consistent once it is done.
+	fn run_test<R>(test: impl FnOnce() -> R) -> R {
+		with_externalities(&mut new_test_ext(), || {
+			let result = test();
+			assert_ok!(KittyModule::check_integrity());
+			result
+		})
+	}
+
 	#[test]
 	fn owned_kitties_can_append_values() {
-		with_externalities(&mut new_test_ext(), || {
-			OwnedKittiesList::<Test>::append(&0, 1);
+		run_test(|| {
+			assert_ok!(OwnedKittiesList::<Test>::append(&0, 1));
 
 			assert_eq!(OwnedKittiesTest::get(&(0, None)), Some(KittyLinkedItem::<Test> {
 				prev: Some(1),
@@ -297,7 +1793,7 @@ mod tests {
 				next: None,
 			}));
 
-			OwnedKittiesList::<Test>::append(&0, 2);
+			assert_ok!(OwnedKittiesList::<Test>::append(&0, 2));
 
 			assert_eq!(OwnedKittiesTest::get(&(0, None)), Some(KittyLinkedItem::<Test> {
 				prev: Some(2),
@@ -314,7 +1810,7 @@ mod tests {
 				next: None,
 			}));
 
-			OwnedKittiesList::<Test>::append(&0, 3);
+			assert_ok!(OwnedKittiesList::<Test>::append(&0, 3));
 
 			assert_eq!(OwnedKittiesTest::get(&(0, None)), Some(KittyLinkedItem::<Test> {
 				prev: Some(3),
@@ -340,12 +1836,12 @@ mod tests {
 
 	#[test]
 	fn owned_kitties_can_remove_values() {
-		with_externalities(&mut new_test_ext(), || {
-			OwnedKittiesList::<Test>::append(&0, 1);
-			OwnedKittiesList::<Test>::append(&0, 2);
-			OwnedKittiesList::<Test>::append(&0, 3);
+		run_test(|| {
+			assert_ok!(OwnedKittiesList::<Test>::append(&0, 1));
+			assert_ok!(OwnedKittiesList::<Test>::append(&0, 2));
+			assert_ok!(OwnedKittiesList::<Test>::append(&0, 3));
 
-			OwnedKittiesList::<Test>::remove(&0, 2);
+			assert_ok!(OwnedKittiesList::<Test>::remove(&0, 2));
 
 			assert_eq!(OwnedKittiesTest::get(&(0, None)), Some(KittyLinkedItem::<Test> {
 				prev: Some(3),
@@ -364,7 +1860,7 @@ mod tests {
 				next: None,
 			}));
 
-			OwnedKittiesList::<Test>::remove(&0, 1);
+			assert_ok!(OwnedKittiesList::<Test>::remove(&0, 1));
 
 			assert_eq!(OwnedKittiesTest::get(&(0, None)), Some(KittyLinkedItem::<Test> {
 				prev: Some(3),
@@ -380,7 +1876,7 @@ mod tests {
 				next: None,
 			}));
 
-			OwnedKittiesList::<Test>::remove(&0, 3);
+			assert_ok!(OwnedKittiesList::<Test>::remove(&0, 3));
 
 			assert_eq!(OwnedKittiesTest::get(&(0, None)), Some(KittyLinkedItem::<Test> {
 				prev: None,
@@ -394,4 +1890,1134 @@ mod tests {
 			assert_eq!(OwnedKittiesTest::get(&(0, Some(2))), None);
 		});
 	}
+
+	#[test]
+	fn owned_kitties_rejects_duplicate_append() {
+		run_test(|| {
+			assert_ok!(OwnedKittiesList::<Test>::append(&0, 1));
+			assert_ok!(OwnedKittiesList::<Test>::append(&0, 2));
+
+			assert_eq!(OwnedKittiesList::<Test>::append(&0, 1), Err(LinkedListError::AlreadyPresent));
+
+			assert_eq!(OwnedKittiesTest::get(&(0, None)), Some(KittyLinkedItem::<Test> {
+				prev: Some(2),
+				next: Some(1),
+			}));
+
+			assert_eq!(OwnedKittiesTest::get(&(0, Some(1))), Some(KittyLinkedItem::<Test> {
+				prev: None,
+				next: Some(2),
+			}));
+		});
+	}
+
+	#[test]
+	fn owned_kitties_rejects_missing_remove() {
+		run_test(|| {
+			assert_eq!(OwnedKittiesList::<Test>::remove(&0, 1), Err(LinkedListError::NotFound));
+
+			assert_ok!(OwnedKittiesList::<Test>::append(&0, 1));
+
+			assert_eq!(OwnedKittiesList::<Test>::remove(&1, 1), Err(LinkedListError::NotFound));
+			assert_ok!(OwnedKittiesList::<Test>::remove(&0, 1));
+			assert_eq!(OwnedKittiesList::<Test>::remove(&0, 1), Err(LinkedListError::NotFound));
+		});
+	}
+
+	#[test]
+	fn transfer_rejects_kitty_already_linked_to_receiver() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			// Simulate a corrupted index where the kitty is also linked into account 2's list
+			assert_ok!(OwnedKittiesList::<Test>::append(&2, 0));
+
+			assert_noop!(KittyModule::transfer(Origin::signed(1), 2, 0), Error::KittyAlreadyInList);
+
+			assert!(OwnedKittiesList::<Test>::contains(&1, 0));
+			assert_eq!(KittyModule::kitty_owner(0), Some(1));
+		});
+	}
+
+	#[test]
+	fn transfer_moves_kitty_between_lists() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			assert_ok!(KittyModule::transfer(Origin::signed(1), 2, 0));
+
+			assert!(!OwnedKittiesList::<Test>::contains(&1, 0));
+			assert!(OwnedKittiesList::<Test>::contains(&2, 0));
+			assert_eq!(KittyModule::kitty_owner(0), Some(2));
+
+			assert_noop!(KittyModule::transfer(Origin::signed(1), 3, 0), Error::NotOwner);
+		});
+	}
+
+	#[test]
+	fn check_integrity_detects_inconsistent_indexes() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(2)));
+			assert_ok!(KittyModule::check_integrity());
+
+			// Kitty 0 linked into a second owner's list
+			assert_ok!(OwnedKittiesList::<Test>::append(&2, 0));
+			assert_eq!(KittyModule::check_integrity(), Err("Owned list contains a kitty of another owner"));
+			assert_ok!(OwnedKittiesList::<Test>::remove(&2, 0));
+
+			// Kitty 0 left behind in the list of an account that owns no kitty
+			assert_ok!(OwnedKittiesList::<Test>::append(&3, 0));
+			assert_eq!(KittyModule::check_integrity(), Err("Owned list contains a kitty of another owner"));
+			assert_ok!(OwnedKittiesList::<Test>::remove(&3, 0));
+
+			// Owner without the matching list entry
+			<KittyOwners<Test>>::insert(0, 3);
+			assert_eq!(KittyModule::check_integrity(), Err("Kitty missing from owner's list"));
+			<KittyOwners<Test>>::insert(0, 1);
+
+			// Price for a kitty that does not exist
+			<KittyPrices<Test>>::insert(2, 10);
+			assert_eq!(KittyModule::check_integrity(), Err("Price recorded at KittiesCount"));
+			<KittyPrices<Test>>::remove(2);
+
+			// Listings further above the count, with and without a price
+			assert_ok!(ListingsList::<Test>::append(&None, 5));
+			assert_eq!(KittyModule::check_integrity(), Err("Listing for a kitty at or above KittiesCount"));
+			assert_ok!(ListingsList::<Test>::remove(&None, 5));
+			assert_ok!(ListingsList::<Test>::append(&Some(1), 5));
+			<KittyPrices<Test>>::insert(5, 10);
+			assert_eq!(KittyModule::check_integrity(), Err("Listing for a kitty at or above KittiesCount"));
+			assert_ok!(ListingsList::<Test>::remove(&Some(1), 5));
+			<KittyPrices<Test>>::remove(5);
+
+			// Count running ahead of the stored kitties
+			<KittiesCount<Test>>::put(3);
+			assert_eq!(KittyModule::check_integrity(), Err("Kitty missing below KittiesCount"));
+			<KittiesCount<Test>>::put(2);
+
+			assert_ok!(KittyModule::check_integrity());
+		});
+	}
+
+	// Lesson-5 shaped state: kitties and owned lists, but no `KittyOwners`
+	fn insert_lesson_5_kitties(kitties: &[(u32, u64)]) {
+		for (kitty_id, owner) in kitties {
+			<Kitties<Test>>::insert(kitty_id, Kitty([*kitty_id as u8; 16]));
+			assert_ok!(OwnedKittiesList::<Test>::append(owner, *kitty_id));
+		}
+		<KittiesCount<Test>>::put(kitties.len() as u32);
+	}
+
+	// Runs blocks until the storage migration is done, returns the number of blocks it took
+	fn run_migration() -> u64 {
+		let mut block = 0;
+		while KittyModule::storage_version() < CURRENT_STORAGE_VERSION {
+			block += 1;
+			assert!(block < 100, "Migration does not finish");
+			KittyModule::on_initialize(block);
+		}
+		block
+	}
+
+	#[test]
+	fn migration_rebuilds_owners_from_lesson_5_layout() {
+		run_test(|| {
+			insert_lesson_5_kitties(&[(0, 1), (1, 2), (2, 1), (3, 3)]);
+
+			assert_eq!(KittyModule::storage_version(), 0);
+			assert_eq!(KittyModule::check_integrity(), Err("Kitty has no owner"));
+
+			run_migration();
+
+			assert_eq!(KittyModule::storage_version(), CURRENT_STORAGE_VERSION);
+			assert_eq!(KittyModule::kitty_owner(0), Some(1));
+			assert_eq!(KittyModule::kitty_owner(1), Some(2));
+			assert_eq!(KittyModule::kitty_owner(2), Some(1));
+			assert_eq!(KittyModule::kitty_owner(3), Some(3));
+			assert_eq!(KittyModule::kitty_price(0), None);
+
+			// Kitties keep working with the migrated indexes
+			assert_ok!(KittyModule::transfer(Origin::signed(1), 2, 2));
+			assert_eq!(KittyModule::kitty_owner(2), Some(2));
+		});
+	}
+
+	#[test]
+	fn migration_is_spread_over_blocks() {
+		run_test(|| {
+			let kitties: Vec<_> = (0..MIGRATION_BATCH + 5).map(|kitty_id| (kitty_id, 1)).collect();
+			insert_lesson_5_kitties(&kitties);
+			<KittyPrices<Test>>::insert(MIGRATION_BATCH + 2, 10);
+
+			// The first block stops in the middle of account 1's list
+			KittyModule::on_initialize(1);
+			assert_eq!(KittyModule::storage_version(), 0);
+			assert_eq!(KittyModule::migration_progress(), Some(MigrationCursor::Accounts {
+				batch: 0,
+				account: 0,
+				after: Some(MIGRATION_BATCH - 1),
+			}));
+			assert_eq!(KittyModule::kitty_owner(MIGRATION_BATCH - 1), Some(1));
+			assert_eq!(KittyModule::kitty_owner(MIGRATION_BATCH), None);
+
+			// The rest of the list and the other known accounts fit in the second block
+			KittyModule::on_initialize(2);
+			assert_eq!(KittyModule::storage_version(), 1);
+			assert_eq!(KittyModule::migration_progress(), None);
+
+			// Versions 2 and 3 visit the kitties by index, two blocks each
+			assert_eq!(run_migration(), 4);
+			assert_eq!(KittyModule::kitty_owner(MIGRATION_BATCH + 4), Some(1));
+			assert_eq!(KittyModule::kitty_lock(MIGRATION_BATCH + 2), Some(LockReason::Listed));
+			assert_eq!(KittyModule::listings_page(None, None, 10), vec![(MIGRATION_BATCH + 2, 10)]);
+		});
+	}
+
+	#[test]
+	fn migration_runs_only_once() {
+		run_test(|| {
+			run_migration();
+			assert_eq!(KittyModule::storage_version(), CURRENT_STORAGE_VERSION);
+
+			insert_lesson_5_kitties(&[(0, 1)]);
+
+			KittyModule::on_initialize(10);
+
+			assert_eq!(KittyModule::kitty_owner(0), None);
+
+			<KittyOwners<Test>>::insert(0, 1);
+		});
+	}
+
+	#[test]
+	fn dispatchables_return_module_errors() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(2)));
+
+			assert_noop!(KittyModule::breed(Origin::signed(1), 0, 5), Error::InvalidKittyId);
+			assert_noop!(KittyModule::breed(Origin::signed(1), 0, 0), Error::RequireDifferentParent);
+			assert_noop!(KittyModule::breed(Origin::signed(1), 0, 2), Error::NotOwner);
+
+			assert_noop!(KittyModule::ask(Origin::signed(2), 0, Some(10)), Error::NotOwner);
+
+			assert_noop!(KittyModule::buy(Origin::signed(2), 5, 0, 10), Error::InvalidKittyId);
+			assert_noop!(KittyModule::buy(Origin::signed(2), 0, 0, 10), Error::NotForSale);
+
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(10)));
+			assert_noop!(KittyModule::buy(Origin::signed(2), 0, 1, 5), Error::PriceTooLow);
+			assert_noop!(KittyModule::buy(Origin::signed(2), 0, 0, 10), Error::ListingChanged);
+			assert_noop!(KittyModule::buy(Origin::signed(1), 0, 1, 10), Error::BuyerIsOwner);
+		});
+	}
+
+	#[test]
+	fn create_fails_when_kitties_count_overflows() {
+		run_test(|| {
+			<KittiesCount<Test>>::put(u32::max_value());
+
+			assert_noop!(KittyModule::create(Origin::signed(1)), Error::KittiesCountOverflow);
+
+			<KittiesCount<Test>>::put(0);
+		});
+	}
+
+	// Gives accounts 1 and 2 funds and long owned lists and listings, so that no call works
+	// on empty ones
+	fn fill_lists() {
+		let _ = balances::Module::<Test>::make_free_balance_be(&1, 1_000_000_000);
+		let _ = balances::Module::<Test>::make_free_balance_be(&2, 1_000_000_000);
+		for kitty_id in 0..100 {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(2)));
+			assert_ok!(KittyModule::ask(Origin::signed(2), 2 * kitty_id + 1, Some(10)));
+		}
+	}
+
+	// Fills every expiry queue of block 10: commitments made at 0, rentals until 10, and
+	// loans funded and offers made at 5
+	fn fill_expiry_queues() {
+		let _ = balances::Module::<Test>::make_free_balance_be(&2, 1_000_000_000);
+		let full = MAX_EXPIRIES_PER_BLOCK as u32;
+
+		for account in 100..100 + u64::from(full) {
+			assert_ok!(KittyModule::commit_create(Origin::signed(account), H256::repeat_byte(1)));
+		}
+		for kitty_id in 0..3 * full {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			if kitty_id < full {
+				assert_ok!(KittyModule::lend(Origin::signed(1), kitty_id, 2, 10, 0));
+			} else if kitty_id < 2 * full {
+				assert_ok!(KittyModule::request_loan(Origin::signed(1), kitty_id, 1, 1, 5));
+			}
+		}
+		assert_noop!(KittyModule::lend(Origin::signed(1), 3 * full - 1, 2, 10, 0), Error::ExpiryQueueFull);
+		assert_noop!(KittyModule::commit_create(Origin::signed(1), H256::repeat_byte(1)), Error::ExpiryQueueFull);
+
+		system::Module::<Test>::set_block_number(5);
+		for kitty_id in full..2 * full {
+			assert_ok!(KittyModule::fund_loan(Origin::signed(2), kitty_id, 1, 1, 5));
+		}
+		for kitty_id in 2 * full..3 * full {
+			assert_ok!(KittyModule::offer_transfer(Origin::signed(1), 3, kitty_id));
+		}
+	}
+
+	// Runs `f` and returns how many storage items outside `System` it changed and how many
+	// events it deposited. Items written more than once, or rewritten with the value they
+	// had, count once or not at all, so this is a lower bound on the writes.
+	fn count_writes(ext: &mut runtime_io::TestExternalities<Blake2Hasher>, f: impl FnOnce()) -> (Weight, Weight) {
+		let system_items = [twox_128(b"System Events"), twox_128(b"System EventCount")];
+		let before: BTreeMap<_, _> = ext.iter_pairs_in_order().collect();
+		let events_before = with_externalities(ext, || system::Module::<Test>::events().len());
+		with_externalities(ext, f);
+		let events = with_externalities(ext, || system::Module::<Test>::events().len()) - events_before;
+		let after: BTreeMap<_, _> = ext.iter_pairs_in_order().collect();
+
+		let writes = before.keys().chain(after.keys())
+			.collect::<BTreeSet<_>>()
+			.into_iter()
+			.filter(|key| system_items.iter().all(|item| item[..] != key[..]))
+			.filter(|key| before.get(*key) != after.get(*key))
+			.count();
+		(writes as Weight, events as Weight)
+	}
+
+	// Dispatches `call` from `sender` and checks the storage it writes and the events it
+	// deposits fit in the weight it declares.
+	fn assert_writes_within_weight(ext: &mut runtime_io::TestExternalities<Blake2Hasher>, sender: u64, call: Call<Test>) {
+		let weight = call.get_dispatch_info().weight;
+		let (writes, events) = count_writes(ext, || assert_ok!(call.dispatch(Origin::signed(sender))));
+		let used = weights::BASE + writes * weights::WRITE + events * weights::EVENT;
+		assert!(used <= weight, "Call writes {} items and deposits {} events, {} more than its weight of {}", writes, events, used - weight, weight);
+	}
+
+	#[test]
+	fn calls_write_within_their_weight() {
+		let mut ext = new_test_ext();
+		with_externalities(&mut ext, fill_lists);
+
+		assert_writes_within_weight(&mut ext, 1, Call::create());
+		assert_writes_within_weight(&mut ext, 1, Call::breed(0, 2));
+		assert_writes_within_weight(&mut ext, 1, Call::transfer(2, 0));
+		assert_writes_within_weight(&mut ext, 2, Call::ask(0, Some(10)));
+		let nonce = with_externalities(&mut ext, || KittyModule::listing_nonce(0));
+		assert_writes_within_weight(&mut ext, 1, Call::buy(0, nonce, 10));
+		assert_writes_within_weight(&mut ext, 100, Call::commit_create(H256::repeat_byte(1)));
+		assert_writes_within_weight(&mut ext, 1, Call::lend(0, 2, 1_000, 0));
+
+		with_externalities(&mut ext, || assert_ok!(KittyModule::check_integrity()));
+	}
+
+	#[test]
+	fn on_finalize_writes_within_its_weight() {
+		let mut ext = new_test_ext();
+		with_externalities(&mut ext, fill_expiry_queues);
+
+		let (writes, events) = count_writes(&mut ext, || KittyModule::on_finalize(10));
+		assert!(writes * weights::WRITE + events * weights::EVENT <= weights::ON_FINALIZE_MAX);
+
+		with_externalities(&mut ext, || {
+			let full = MAX_EXPIRIES_PER_BLOCK as u32;
+			assert_eq!(KittyModule::create_commit(100), None);
+			for kitty_id in 0..3 * full {
+				assert_eq!(KittyModule::kitty_lock(kitty_id), None);
+			}
+			assert_eq!(KittyModule::kitty_owner(full), Some(2));
+			assert_ok!(KittyModule::check_integrity());
+		});
+	}
+
+	// Number of times each call runs in `benchmark_weights`
+	const TIMED_RUNS: u32 = 200;
+
+	// Dispatches the call made by `call(run)` `TIMED_RUNS` times, after an untimed `setup(run)`,
+	// and prints its average time in microseconds next to the weight it declares.
+	fn benchmark(name: &str, setup: impl Fn(u32), call: impl Fn(u32) -> (u64, Call<Test>)) {
+		let mut elapsed = Duration::default();
+		let mut weight = 0;
+		for run in 0..TIMED_RUNS {
+			setup(run);
+			let (sender, call) = call(run);
+			weight = call.get_dispatch_info().weight;
+			let started = Instant::now();
+			let result = call.dispatch(Origin::signed(sender));
+			elapsed += started.elapsed();
+			assert_ok!(result);
+		}
+		let average = elapsed.as_micros() / u128::from(TIMED_RUNS);
+		println!("{:<16} {:>8} µs {:>8} weight", name, average, weight);
+	}
+
+	// Times the calls and `on_finalize` against in-memory storage and prints the results next
+	// to their weights. Timings depend on the machine, so this only reports them, run it with
+	// `cargo test -p substrate-kitties-runtime --release -- --ignored --nocapture benchmark_weights`.
+	#[test]
+	#[ignore]
+	fn benchmark_weights() {
+		run_test(|| {
+			fill_lists();
+			let alternate = |run: u32| if run % 2 == 0 { (1, 2) } else { (2, 1) };
+
+			benchmark("create", |_| {}, |_| (1, Call::create()));
+			benchmark("breed", |_| {}, |_| (1, Call::breed(0, 2)));
+			benchmark("transfer", |_| {}, |run| {
+				let (from, to) = alternate(run);
+				(from, Call::transfer(to, 0))
+			});
+			benchmark(
+				"ask",
+				|_| assert_ok!(KittyModule::ask(Origin::signed(1), 0, None)),
+				|_| (1, Call::ask(0, Some(10))),
+			);
+			benchmark(
+				"buy",
+				|run| assert_ok!(KittyModule::ask(Origin::signed(alternate(run).0), 0, Some(10))),
+				|run| (alternate(run).1, Call::buy(0, KittyModule::listing_nonce(0), 10)),
+			);
+			benchmark(
+				"commit_create",
+				|run| system::Module::<Test>::set_block_number(u64::from(run)),
+				|run| (100 + u64::from(run), Call::commit_create(H256::repeat_byte(1))),
+			);
+			benchmark(
+				"lend",
+				|run| if run > 0 { assert_ok!(KittyModule::return_kitty(Origin::signed(1), 0)) },
+				|run| (1, Call::lend(0, 2, 1_000 + u64::from(run), 0)),
+			);
+			assert_ok!(KittyModule::return_kitty(Origin::signed(1), 0));
+		});
+
+		run_test(|| {
+			fill_expiry_queues();
+			let started = Instant::now();
+			KittyModule::on_finalize(10);
+			println!("{:<16} {:>8} µs {:>8} weight", "on_finalize", started.elapsed().as_micros(), weights::ON_FINALIZE_MAX);
+		});
+	}
+
+	#[test]
+	fn items_that_end_early_leave_their_expiry_queue() {
+		run_test(|| {
+			let full = MAX_EXPIRIES_PER_BLOCK as u32;
+			for _ in 0..2 * full + 2 {
+				assert_ok!(KittyModule::create(Origin::signed(1)));
+			}
+
+			// Rentals until 10 and offers expiring at 5 fill both queues
+			for kitty_id in 0..full {
+				assert_ok!(KittyModule::lend(Origin::signed(1), kitty_id, 2, 10, 0));
+				assert_ok!(KittyModule::offer_transfer(Origin::signed(1), 3, full + kitty_id));
+			}
+			assert_noop!(KittyModule::lend(Origin::signed(1), 2 * full, 2, 10, 0), Error::ExpiryQueueFull);
+			assert_noop!(KittyModule::offer_transfer(Origin::signed(1), 3, 2 * full + 1), Error::ExpiryQueueFull);
+
+			assert_ok!(KittyModule::return_kitty(Origin::signed(1), 0));
+			assert_ok!(KittyModule::cancel_transfer(Origin::signed(1), full));
+			assert_ok!(KittyModule::accept_transfer(Origin::signed(3), full + 1));
+			assert!(!KittyModule::rental_expiries(10).contains(&0));
+			assert!(!KittyModule::transfer_offer_expiries(5).contains(&full));
+			assert!(!KittyModule::transfer_offer_expiries(5).contains(&(full + 1)));
+
+			assert_ok!(KittyModule::lend(Origin::signed(1), 2 * full, 2, 10, 0));
+			assert_ok!(KittyModule::offer_transfer(Origin::signed(1), 3, 2 * full + 1));
+			assert_eq!(KittyModule::rental_expiries(10).len(), MAX_EXPIRIES_PER_BLOCK);
+			assert_eq!(KittyModule::transfer_offer_expiries(5).len(), MAX_EXPIRIES_PER_BLOCK - 1);
+
+			// A revealed commitment leaves the queue of the block it would have expired at
+			let salt = H256::repeat_byte(7);
+			assert_ok!(KittyModule::commit_create(Origin::signed(1), commitment(1, salt)));
+			start_block(2);
+			assert_ok!(KittyModule::reveal_create(Origin::signed(1), salt));
+			assert_eq!(KittyModule::commit_expiries(10), Vec::<u64>::new());
+		});
+	}
+
+	#[test]
+	fn dna_comes_from_the_randomness_source() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(2)));
+
+			let expected = |sender: u64| (sender, Option::<u32>::None, 0u64).using_encoded(blake2_128);
+			assert_eq!(KittyModule::kitty(0).unwrap().0, expected(1));
+			assert_eq!(KittyModule::kitty(1).unwrap().0, expected(2));
+			assert_ne!(expected(1), expected(2));
+		});
+	}
+
+	fn commitment(sender: u64, salt: H256) -> H256 {
+		BlakeTwo256::hash_of(&(sender, salt))
+	}
+
+	// Starts block `n` the way the executive does, as far as the kitties module cares
+	fn start_block(n: u64) {
+		system::Module::<Test>::set_block_number(n);
+		KittyModule::on_initialize(n);
+	}
+
+	#[test]
+	fn commit_reveal_mints_kitty() {
+		run_test(|| {
+			let salt = H256::repeat_byte(7);
+			assert_ok!(KittyModule::commit_create(Origin::signed(1), commitment(1, salt)));
+			assert_noop!(KittyModule::commit_create(Origin::signed(1), commitment(1, salt)), Error::CommitAlreadyPending);
+			assert_eq!(KittyModule::reveal_targets(2), 1);
+
+			start_block(1);
+			assert_noop!(KittyModule::reveal_create(Origin::signed(1), salt), Error::RevealTooEarly);
+			assert_eq!(KittyModule::reveal_randomness(2), None);
+
+			start_block(2);
+			assert_noop!(KittyModule::reveal_create(Origin::signed(2), salt), Error::NoPendingCommit);
+			assert_noop!(KittyModule::reveal_create(Origin::signed(1), H256::repeat_byte(8)), Error::InvalidReveal);
+
+			assert_ok!(KittyModule::reveal_create(Origin::signed(1), salt));
+
+			let randomness = (REVEAL_SUBJECT, 2u64).using_encoded(blake2_128);
+			let expected = (salt, 1u64, randomness).using_encoded(blake2_128);
+			assert_eq!(KittyModule::kitty(0).unwrap().0, expected);
+			assert_eq!(KittyModule::kitty_owner(0), Some(1));
+			assert_eq!(KittyModule::create_commit(1), None);
+			// Nothing else reveals against block 2
+			assert_eq!(KittyModule::reveal_targets(2), 0);
+			assert_eq!(KittyModule::reveal_randomness(2), None);
+
+			// A fresh commitment can follow a reveal
+			assert_ok!(KittyModule::commit_create(Origin::signed(1), commitment(1, salt)));
+		});
+	}
+
+	#[test]
+	fn reveal_block_does_not_change_the_dna() {
+		let salt = H256::repeat_byte(7);
+		let reveal_at = |block: u64| run_test(|| {
+			assert_ok!(KittyModule::commit_create(Origin::signed(1), commitment(1, salt)));
+			assert_ok!(KittyModule::commit_create(Origin::signed(2), commitment(2, salt)));
+			for n in 1..=block {
+				start_block(n);
+			}
+			assert_ok!(KittyModule::reveal_create(Origin::signed(1), salt));
+			// The other commitment still needs the randomness
+			assert!(KittyModule::reveal_randomness(2).is_some());
+			KittyModule::kitty(0).unwrap().0
+		});
+
+		assert_eq!(reveal_at(2), reveal_at(9));
+	}
+
+	#[test]
+	fn commits_without_reveal_randomness_can_only_expire() {
+		run_test(|| {
+			let salt = H256::repeat_byte(7);
+			assert_ok!(KittyModule::commit_create(Origin::signed(1), commitment(1, salt)));
+
+			// Block 2 started without drawing the randomness, like for a commitment made
+			// before the runtime drew any
+			<RevealTargets<Test>>::remove(2);
+			start_block(2);
+			assert_noop!(KittyModule::reveal_create(Origin::signed(1), salt), Error::NoRevealRandomness);
+
+			KittyModule::on_finalize(10);
+			assert_eq!(KittyModule::create_commit(1), None);
+			assert_eq!(KittyModule::reveal_targets(2), 0);
+		});
+	}
+
+	#[test]
+	fn commits_expire_on_finalize() {
+		run_test(|| {
+			let salt = H256::repeat_byte(7);
+			assert_ok!(KittyModule::commit_create(Origin::signed(1), commitment(1, salt)));
+
+			system::Module::<Test>::set_block_number(5);
+			assert_ok!(KittyModule::commit_create(Origin::signed(2), commitment(2, salt)));
+
+			system::Module::<Test>::set_block_number(10);
+			assert_noop!(KittyModule::reveal_create(Origin::signed(1), salt), Error::CommitExpired);
+
+			KittyModule::on_finalize(10);
+			assert_eq!(KittyModule::create_commit(1), None);
+			assert!(KittyModule::create_commit(2).is_some());
+			assert!(KittyModule::commit_expiries(10).is_empty());
+			assert_eq!(KittyModule::reveal_targets(2), 0);
+			assert_eq!(KittyModule::reveal_targets(7), 1);
+
+			KittyModule::on_finalize(15);
+			assert_eq!(KittyModule::create_commit(2), None);
+			assert_eq!(KittyModule::reveal_targets(7), 0);
+		});
+	}
+
+	#[test]
+	fn expiry_ignores_replaced_commit() {
+		run_test(|| {
+			let salt = H256::repeat_byte(7);
+			assert_ok!(KittyModule::commit_create(Origin::signed(1), commitment(1, salt)));
+
+			start_block(2);
+			assert_ok!(KittyModule::reveal_create(Origin::signed(1), salt));
+			assert_ok!(KittyModule::commit_create(Origin::signed(1), commitment(1, salt)));
+
+			// The first commitment's expiry must not remove the second one, nor its reveal target
+			KittyModule::on_finalize(10);
+			assert!(KittyModule::create_commit(1).is_some());
+			assert_eq!(KittyModule::reveal_targets(4), 1);
+
+			KittyModule::on_finalize(12);
+			assert_eq!(KittyModule::create_commit(1), None);
+		});
+	}
+
+	#[test]
+	fn lend_borrow_and_reclaim() {
+		run_test(|| {
+			let _ = balances::Module::<Test>::make_free_balance_be(&2, 100);
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(2)));
+
+			assert_noop!(KittyModule::lend(Origin::signed(2), 0, 2, 5, 10), Error::NotOwner);
+			assert_noop!(KittyModule::lend(Origin::signed(1), 0, 2, 0, 10), Error::InvalidRentalPeriod);
+			assert_ok!(KittyModule::lend(Origin::signed(1), 0, 2, 5, 10));
+			assert_noop!(KittyModule::lend(Origin::signed(1), 0, 3, 5, 10), Error::KittyRented);
+
+			// The owner still holds the kitty until the offer is accepted
+			assert_eq!(KittyModule::kitty_holder(0), Some(1));
+			assert_noop!(KittyModule::borrow(Origin::signed(3), 0), Error::NotBorrower);
+			assert_ok!(KittyModule::borrow(Origin::signed(2), 0));
+			assert_noop!(KittyModule::borrow(Origin::signed(2), 0), Error::RentalAlreadyActive);
+
+			assert_eq!(balances::Module::<Test>::free_balance(&1), 10);
+			assert_eq!(balances::Module::<Test>::free_balance(&2), 90);
+			assert_eq!(KittyModule::kitty_owner(0), Some(1));
+			assert_eq!(KittyModule::kitty_holder(0), Some(2));
+
+			// The borrower can breed with it, but nobody can move or sell it
+			assert_ok!(KittyModule::breed(Origin::signed(2), 0, 1));
+			assert_eq!(KittyModule::kitty_owner(2), Some(2));
+			assert_noop!(KittyModule::breed(Origin::signed(1), 0, 2), Error::NotOwner);
+			assert_noop!(KittyModule::transfer(Origin::signed(1), 3, 0), Error::KittyRented);
+			assert_noop!(KittyModule::transfer(Origin::signed(2), 3, 0), Error::NotOwner);
+			assert_noop!(KittyModule::ask(Origin::signed(1), 0, Some(10)), Error::KittyRented);
+			assert_noop!(KittyModule::return_kitty(Origin::signed(1), 0), Error::RentalAlreadyActive);
+
+			KittyModule::on_finalize(5);
+
+			assert_eq!(KittyModule::kitty_rental(0), None);
+			assert_eq!(KittyModule::kitty_holder(0), Some(1));
+			assert_ok!(KittyModule::transfer(Origin::signed(1), 3, 0));
+		});
+	}
+
+	#[test]
+	fn rentals_can_end_early() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			// The owner withdraws an offer that was not accepted
+			assert_ok!(KittyModule::lend(Origin::signed(1), 0, 2, 5, 0));
+			assert_ok!(KittyModule::return_kitty(Origin::signed(1), 0));
+			assert_eq!(KittyModule::kitty_rental(0), None);
+
+			// The borrower hands it back before the end
+			assert_ok!(KittyModule::lend(Origin::signed(1), 0, 2, 5, 0));
+			assert_ok!(KittyModule::borrow(Origin::signed(2), 0));
+			assert_noop!(KittyModule::return_kitty(Origin::signed(3), 0), Error::NotBorrower);
+			assert_ok!(KittyModule::return_kitty(Origin::signed(2), 0));
+			assert_eq!(KittyModule::kitty_holder(0), Some(1));
+
+			// A new rental is not cut short by the expiry of the earlier one
+			assert_ok!(KittyModule::lend(Origin::signed(1), 0, 2, 8, 0));
+			KittyModule::on_finalize(5);
+			assert!(KittyModule::kitty_rental(0).is_some());
+			KittyModule::on_finalize(8);
+			assert_eq!(KittyModule::kitty_rental(0), None);
+			assert_noop!(KittyModule::return_kitty(Origin::signed(2), 0), Error::NoRental);
+		});
+	}
+
+	#[test]
+	fn pledged_kitty_is_locked_until_repaid() {
+		run_test(|| {
+			let _ = balances::Module::<Test>::make_free_balance_be(&2, 1_000);
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			assert_noop!(KittyModule::request_loan(Origin::signed(2), 0, 100, 10, 5), Error::NotOwner);
+			assert_ok!(KittyModule::request_loan(Origin::signed(1), 0, 100, 10, 5));
+
+			assert_noop!(KittyModule::transfer(Origin::signed(1), 3, 0), Error::KittyPledged);
+			assert_noop!(KittyModule::ask(Origin::signed(1), 0, Some(10)), Error::KittyPledged);
+			assert_noop!(KittyModule::breed(Origin::signed(1), 0, 1), Error::KittyPledged);
+			assert_noop!(KittyModule::lend(Origin::signed(1), 0, 2, 5, 0), Error::KittyPledged);
+			assert_noop!(KittyModule::repay_loan(Origin::signed(1), 0), Error::LoanNotFunded);
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(KittyModule::fund_loan(Origin::signed(2), 0, 100, 10, 5));
+			assert_noop!(KittyModule::fund_loan(Origin::signed(3), 0, 100, 10, 5), Error::LoanAlreadyFunded);
+			assert_noop!(KittyModule::cancel_loan_request(Origin::signed(1), 0), Error::LoanAlreadyFunded);
+			assert_eq!(balances::Module::<Test>::free_balance(&1), 100);
+			assert_eq!(balances::Module::<Test>::free_balance(&2), 900);
+
+			let _ = balances::Module::<Test>::make_free_balance_be(&1, 110);
+			assert_ok!(KittyModule::repay_loan(Origin::signed(1), 0));
+			assert_eq!(balances::Module::<Test>::free_balance(&1), 0);
+			assert_eq!(balances::Module::<Test>::free_balance(&2), 1_010);
+			assert_eq!(KittyModule::kitty_loan(0), None);
+
+			// The old deadline does not affect the repaid kitty
+			KittyModule::on_finalize(6);
+			assert_eq!(KittyModule::kitty_owner(0), Some(1));
+			assert_ok!(KittyModule::breed(Origin::signed(1), 0, 1));
+		});
+	}
+
+	#[test]
+	fn overdue_collateral_goes_to_lender() {
+		run_test(|| {
+			let _ = balances::Module::<Test>::make_free_balance_be(&2, 1_000);
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			assert_ok!(KittyModule::request_loan(Origin::signed(1), 0, 100, 10, 5));
+			assert_ok!(KittyModule::fund_loan(Origin::signed(2), 0, 100, 10, 5));
+
+			KittyModule::on_finalize(4);
+			assert_eq!(KittyModule::kitty_owner(0), Some(1));
+
+			KittyModule::on_finalize(5);
+			assert_eq!(KittyModule::kitty_owner(0), Some(2));
+			assert_eq!(KittyModule::kitty_loan(0), None);
+			assert_ok!(KittyModule::transfer(Origin::signed(2), 3, 0));
+		});
+	}
+
+	#[test]
+	fn loan_request_can_be_cancelled() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			assert_ok!(KittyModule::request_loan(Origin::signed(1), 0, 100, 10, 5));
+			assert_noop!(KittyModule::request_loan(Origin::signed(1), 0, 100, 10, 5), Error::KittyPledged);
+			assert_noop!(KittyModule::request_loan(Origin::signed(1), 1, 100, 10, 5), Error::NotOwner);
+			assert_ok!(KittyModule::cancel_loan_request(Origin::signed(1), 0));
+			assert_noop!(KittyModule::cancel_loan_request(Origin::signed(1), 0), Error::NoLoan);
+
+			assert_noop!(KittyModule::request_loan(Origin::signed(1), 0, u64::max_value(), 1, 5), Error::LoanOverflow);
+			assert_noop!(KittyModule::request_loan(Origin::signed(1), 0, 100, 10, 0), Error::InvalidLoanDuration);
+			assert_noop!(KittyModule::request_loan(Origin::signed(1), 0, 100, 10, 101), Error::InvalidLoanDuration);
+			assert_ok!(KittyModule::transfer(Origin::signed(1), 2, 0));
+		});
+	}
+
+	#[test]
+	fn lender_funds_the_terms_they_saw() {
+		run_test(|| {
+			let _ = balances::Module::<Test>::make_free_balance_be(&2, 1_000);
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::request_loan(Origin::signed(1), 0, 100, 10, 5));
+
+			// The owner swaps the request for harsher terms before the lender's call lands
+			assert_ok!(KittyModule::cancel_loan_request(Origin::signed(1), 0));
+			assert_ok!(KittyModule::request_loan(Origin::signed(1), 0, 100, 10, 1));
+			assert_noop!(KittyModule::fund_loan(Origin::signed(2), 0, 100, 10, 5), Error::LoanTermsChanged);
+			assert_noop!(KittyModule::fund_loan(Origin::signed(2), 0, 90, 10, 1), Error::LoanTermsChanged);
+			assert_noop!(KittyModule::fund_loan(Origin::signed(2), 0, 100, 20, 1), Error::LoanTermsChanged);
+			assert_eq!(balances::Module::<Test>::free_balance(&2), 1_000);
+
+			// A deadline past the last block is refused rather than wrapping around
+			system::Module::<Test>::set_block_number(u64::max_value());
+			assert_noop!(KittyModule::fund_loan(Origin::signed(2), 0, 100, 10, 1), Error::LoanDeadlineOverflow);
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(KittyModule::fund_loan(Origin::signed(2), 0, 100, 10, 1));
+			assert_eq!(KittyModule::kitty_loan(0).unwrap().funded, Some((2, 2)));
+		});
+	}
+
+	#[test]
+	fn listed_kitty_can_not_be_transferred() {
+		run_test(|| {
+			let _ = balances::Module::<Test>::make_free_balance_be(&2, 100);
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(10)));
+			assert_eq!(KittyModule::kitty_lock(0), Some(LockReason::Listed));
+			assert_noop!(KittyModule::transfer(Origin::signed(1), 3, 0), Error::KittyForSale);
+			assert_noop!(KittyModule::lend(Origin::signed(1), 0, 2, 5, 0), Error::KittyForSale);
+			assert_noop!(KittyModule::request_loan(Origin::signed(1), 0, 100, 10, 5), Error::KittyForSale);
+
+			// Repricing keeps the listing, delisting releases it
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(20)));
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, None));
+			assert_eq!(KittyModule::kitty_lock(0), None);
+			assert_ok!(KittyModule::transfer(Origin::signed(1), 3, 0));
+
+			// A sale releases the listing as well
+			assert_ok!(KittyModule::ask(Origin::signed(3), 0, Some(10)));
+			assert_ok!(KittyModule::buy(Origin::signed(2), 0, KittyModule::listing_nonce(0), 10));
+			assert_eq!(KittyModule::kitty_lock(0), None);
+			assert_eq!(KittyModule::kitty_owner(0), Some(2));
+		});
+	}
+
+	#[test]
+	fn locks_decide_breeding() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(10)));
+			assert_ok!(KittyModule::breed(Origin::signed(1), 0, 1));
+
+			for (reason, error) in [
+				(LockReason::Auction, Error::KittyInAuction),
+				(LockReason::Pregnant, Error::KittyPregnant),
+			].iter() {
+				<KittyLocks<Test>>::insert(1, reason);
+				assert_noop!(KittyModule::breed(Origin::signed(1), 0, 1), error.clone());
+				assert_noop!(KittyModule::transfer(Origin::signed(1), 2, 1), error.clone());
+				assert_noop!(KittyModule::ask(Origin::signed(1), 1, Some(10)), error.clone());
+			}
+			<KittyLocks<Test>>::remove(1);
+		});
+	}
+
+	#[test]
+	fn migration_locks_existing_listings() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			StorageVersion::put(1);
+
+			// Version 1 state: prices and rentals without locks
+			<KittyPrices<Test>>::insert(0, 10);
+			<KittyRentals<Test>>::insert(1, Rental { borrower: 2, until: 5, fee: 0, active: true });
+			assert_eq!(KittyModule::check_integrity(), Err("Price and listed lock disagree"));
+
+			assert_eq!(run_migration(), 2);
+
+			assert_eq!(KittyModule::storage_version(), CURRENT_STORAGE_VERSION);
+			assert_eq!(KittyModule::kitty_lock(0), Some(LockReason::Listed));
+			assert_eq!(KittyModule::listings_page(None, None, 10), vec![(0, 10)]);
+			assert_eq!(KittyModule::kitty_lock(1), Some(LockReason::Rental));
+			assert_eq!(KittyModule::kitty_lock(2), None);
+		});
+	}
+
+	#[test]
+	fn offered_kitty_moves_once_accepted() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			assert_ok!(KittyModule::offer_transfer(Origin::signed(1), 2, 0));
+			assert_eq!(KittyModule::transfer_offer(0), Some((2, 5)));
+			assert_eq!(KittyModule::kitty_owner(0), Some(1));
+
+			// Locked while the offer is pending
+			assert_noop!(KittyModule::transfer(Origin::signed(1), 3, 0), Error::KittyOffered);
+			assert_noop!(KittyModule::ask(Origin::signed(1), 0, Some(10)), Error::KittyOffered);
+			assert_noop!(KittyModule::offer_transfer(Origin::signed(1), 3, 0), Error::KittyOffered);
+
+			assert_noop!(KittyModule::accept_transfer(Origin::signed(3), 0), Error::NotRecipient);
+			assert_ok!(KittyModule::accept_transfer(Origin::signed(2), 0));
+
+			assert_eq!(KittyModule::kitty_owner(0), Some(2));
+			assert_eq!(KittyModule::transfer_offer(0), None);
+			assert_eq!(KittyModule::kitty_lock(0), None);
+			assert_noop!(KittyModule::accept_transfer(Origin::signed(2), 0), Error::NoTransferOffer);
+		});
+	}
+
+	#[test]
+	fn transfer_offers_can_be_cancelled_and_expire() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			assert_ok!(KittyModule::offer_transfer(Origin::signed(1), 2, 0));
+			assert_noop!(KittyModule::cancel_transfer(Origin::signed(2), 0), Error::NotOwner);
+			assert_ok!(KittyModule::cancel_transfer(Origin::signed(1), 0));
+			assert_noop!(KittyModule::accept_transfer(Origin::signed(2), 0), Error::NoTransferOffer);
+			assert_noop!(KittyModule::cancel_transfer(Origin::signed(1), 0), Error::NoTransferOffer);
+
+			// A new offer is not cut short by the expiry of the cancelled one
+			system::Module::<Test>::set_block_number(2);
+			assert_ok!(KittyModule::offer_transfer(Origin::signed(1), 3, 0));
+			KittyModule::on_finalize(5);
+			assert_eq!(KittyModule::transfer_offer(0), Some((3, 7)));
+			KittyModule::on_finalize(7);
+			assert_eq!(KittyModule::transfer_offer(0), None);
+			assert_eq!(KittyModule::kitty_lock(0), None);
+			assert_noop!(KittyModule::accept_transfer(Origin::signed(3), 0), Error::NoTransferOffer);
+			assert_eq!(KittyModule::kitty_owner(0), Some(1));
+		});
+	}
+
+	#[test]
+	fn buy_fails_when_the_listing_changed() {
+		run_test(|| {
+			let _ = balances::Module::<Test>::make_free_balance_be(&2, 100);
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(10)));
+			let nonce = KittyModule::listing_nonce(0);
+
+			// The seller raises the price before the buy lands
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(50)));
+			assert_noop!(KittyModule::buy(Origin::signed(2), 0, nonce, 50), Error::ListingChanged);
+
+			// Relisting at the old price still changes the listing
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(10)));
+			assert_noop!(KittyModule::buy(Origin::signed(2), 0, nonce, 10), Error::ListingChanged);
+
+			let nonce = KittyModule::listing_nonce(0);
+			assert_ok!(KittyModule::buy(Origin::signed(2), 0, nonce, 20));
+
+			// Only the asking price is charged
+			assert_eq!(balances::Module::<Test>::free_balance(&2), 90);
+			assert_eq!(KittyModule::kitty_owner(0), Some(2));
+		});
+	}
+
+	#[test]
+	fn listings_are_indexed_by_seller() {
+		run_test(|| {
+			let _ = balances::Module::<Test>::make_free_balance_be(&3, 100);
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(2)));
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(10)));
+			assert_ok!(KittyModule::ask(Origin::signed(2), 1, Some(20)));
+			assert_ok!(KittyModule::ask(Origin::signed(1), 2, Some(30)));
+			// Repricing keeps the place in the listings
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(15)));
+
+			assert_eq!(KittyModule::listings_page(None, None, 10), vec![(0, 15), (1, 20), (2, 30)]);
+			assert_eq!(KittyModule::listings_page(Some(1), None, 10), vec![(0, 15), (2, 30)]);
+			assert_eq!(KittyModule::listings_page(Some(2), None, 10), vec![(1, 20)]);
+			assert_eq!(KittyModule::listings_page(Some(3), None, 10), vec![]);
+
+			// Pages continue after the last kitty of the previous one
+			assert_eq!(KittyModule::listings_page(None, None, 2), vec![(0, 15), (1, 20)]);
+			assert_eq!(KittyModule::listings_page(None, Some(1), 2), vec![(2, 30)]);
+			assert_eq!(KittyModule::listings_page(None, Some(3), 2), vec![]);
+
+			// Delisting and buying both drop the kitty from the listings
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, None));
+			assert_ok!(KittyModule::buy(Origin::signed(3), 1, KittyModule::listing_nonce(1), 20));
+			assert_eq!(KittyModule::listings_page(None, None, 10), vec![(2, 30)]);
+			assert_eq!(KittyModule::listings_page(Some(2), None, 10), vec![]);
+		});
+	}
+
+	#[test]
+	fn details_and_owned_pages_read_storage() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(2)));
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::ask(Origin::signed(1), 2, Some(30)));
+
+			assert_eq!(KittyModule::kitty_details(2), Some(KittyDetails {
+				dna: KittyModule::kitty(2).unwrap().0,
+				owner: 1,
+				price: Some(30),
+				lock: Some(LockReason::Listed),
+			}));
+			assert_eq!(KittyModule::kitty_details(1).map(|details| (details.owner, details.price)), Some((2, None)));
+			assert_eq!(KittyModule::kitty_details(3), None);
+
+			assert_eq!(KittyModule::owned_page(1, None, 10), vec![0, 2]);
+			assert_eq!(KittyModule::owned_page(1, None, 1), vec![0]);
+			assert_eq!(KittyModule::owned_page(1, Some(0), 1), vec![2]);
+			assert_eq!(KittyModule::owned_page(2, None, 10), vec![1]);
+			assert_eq!(KittyModule::owned_page(3, None, 10), vec![]);
+		});
+	}
+
+	#[test]
+	fn genesis_kitties_are_owned_and_listed() {
+		let mut ext: runtime_io::TestExternalities<Blake2Hasher> = GenesisConfig::<Test> {
+			kitties: vec![(1, [1; 16], None), (2, [2; 16], Some(50)), (1, [3; 16], None)],
+		}.build_storage().unwrap().into();
+
+		with_externalities(&mut ext, || {
+			assert_eq!(KittyModule::kitties_count(), 3);
+			assert_eq!(KittyModule::owned_page(1, None, 10), vec![0, 2]);
+			assert_eq!(KittyModule::kitty_details(1), Some(KittyDetails {
+				dna: [2; 16],
+				owner: 2,
+				price: Some(50),
+				lock: Some(LockReason::Listed),
+			}));
+			assert_eq!(KittyModule::listings_page(None, None, 10), vec![(1, 50)]);
+			assert_eq!(KittyModule::storage_version(), CURRENT_STORAGE_VERSION);
+			assert_ok!(KittyModule::check_integrity());
+		});
+	}
+
+	#[test]
+	fn sales_are_recorded_for_price_references() {
+		run_test(|| {
+			let _ = balances::Module::<Test>::make_free_balance_be(&1, 10_000);
+			let _ = balances::Module::<Test>::make_free_balance_be(&2, 10_000);
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			let tier = KittyModule::kitty(0).unwrap().rarity_tier();
+			<Kitties<Test>>::insert(1, Kitty(KittyModule::kitty(0).unwrap().0));
+
+			assert_eq!(KittyModule::reference_price(0), None);
+
+			for i in 0..10u64 {
+				let (seller, buyer) = if i % 2 == 0 { (1, 2) } else { (2, 1) };
+				system::Module::<Test>::set_block_number(i + 1);
+				assert_ok!(KittyModule::ask(Origin::signed(seller), 0, Some(80 + 8 * i)));
+				assert_ok!(KittyModule::buy(Origin::signed(buyer), 0, KittyModule::listing_nonce(0), 200));
+			}
+
+			// Only the most recent sales are kept, oldest first
+			let history = KittyModule::sale_history(0);
+			assert_eq!(history.len(), MAX_SALE_HISTORY);
+			assert_eq!(history[0], Sale { price: 96, block: 3, buyer: 2, seller: 1 });
+			assert_eq!(history[MAX_SALE_HISTORY - 1], Sale { price: 152, block: 10, buyer: 1, seller: 2 });
+
+			// The average starts at the first price and moves an eighth of the way each sale
+			assert_eq!(KittyModule::average_sale_price(tier), Some(115));
+			assert_eq!(KittyModule::reference_price(0), Some(152));
+			// A kitty of the same tier that never sold falls back to the average
+			assert_eq!(KittyModule::reference_price(1), Some(115));
+		});
+	}
+
+	#[test]
+	fn root_can_pause_each_activity() {
+		run_test(|| {
+			let _ = balances::Module::<Test>::make_free_balance_be(&2, 100);
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(10)));
+
+			assert_noop!(KittyModule::set_paused(Origin::signed(1), Activity::Trading, true), Error::BadOrigin);
+			assert_ok!(KittyModule::set_paused(Origin::ROOT, Activity::Trading, true));
+
+			let nonce = KittyModule::listing_nonce(0);
+			assert_noop!(KittyModule::buy(Origin::signed(2), 0, nonce, 10), Error::ActivityPaused);
+			assert_noop!(KittyModule::transfer(Origin::signed(1), 2, 1), Error::ActivityPaused);
+			assert_noop!(KittyModule::ask(Origin::signed(1), 1, Some(10)), Error::ActivityPaused);
+			// Sellers can still take their kitties off sale, and the other activities go on
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, None));
+			assert_ok!(KittyModule::breed(Origin::signed(1), 0, 1));
+
+			assert_ok!(KittyModule::set_paused(Origin::ROOT, Activity::Breeding, true));
+			assert_noop!(KittyModule::breed(Origin::signed(1), 0, 1), Error::ActivityPaused);
+			assert_ok!(KittyModule::set_paused(Origin::ROOT, Activity::Creation, true));
+			assert_noop!(KittyModule::create(Origin::signed(1)), Error::ActivityPaused);
+
+			assert_ok!(KittyModule::set_paused(Origin::ROOT, Activity::Trading, false));
+			assert_ok!(KittyModule::transfer(Origin::signed(1), 2, 1));
+		});
+	}
+
+	#[test]
+	fn blocked_accounts_can_not_trade() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+
+			assert_noop!(KittyModule::set_blocked(Origin::signed(1), 2, true), Error::BadOrigin);
+			assert_ok!(KittyModule::set_blocked(Origin::ROOT, 2, true));
+			assert!(KittyModule::is_blocked(2));
+
+			assert_noop!(KittyModule::create(Origin::signed(2)), Error::AccountBlocked);
+			assert_noop!(KittyModule::transfer(Origin::signed(1), 2, 0), Error::AccountBlocked);
+			assert_noop!(KittyModule::offer_transfer(Origin::signed(1), 2, 0), Error::AccountBlocked);
+
+			assert_ok!(KittyModule::set_blocked(Origin::ROOT, 2, false));
+			assert_ok!(KittyModule::transfer(Origin::signed(1), 2, 0));
+		});
+	}
+
+	#[test]
+	fn blocked_accounts_are_not_paid() {
+		run_test(|| {
+			let _ = balances::Module::<Test>::make_free_balance_be(&2, 1_000);
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::lend(Origin::signed(1), 0, 2, 5, 10));
+			assert_ok!(KittyModule::request_loan(Origin::signed(1), 1, 100, 10, 5));
+
+			assert_ok!(KittyModule::set_blocked(Origin::ROOT, 1, true));
+			assert_noop!(KittyModule::borrow(Origin::signed(2), 0), Error::AccountBlocked);
+			assert_noop!(KittyModule::fund_loan(Origin::signed(2), 1, 100, 10, 5), Error::AccountBlocked);
+			assert_eq!(balances::Module::<Test>::free_balance(&1), 0);
+
+			assert_ok!(KittyModule::set_blocked(Origin::ROOT, 1, false));
+			assert_ok!(KittyModule::borrow(Origin::signed(2), 0));
+			assert_ok!(KittyModule::fund_loan(Origin::signed(2), 1, 100, 10, 5));
+			assert_eq!(balances::Module::<Test>::free_balance(&1), 110);
+		});
+	}
+
+	#[test]
+	fn force_transfer_ends_whatever_holds_the_kitty() {
+		run_test(|| {
+			let _ = balances::Module::<Test>::make_free_balance_be(&2, 1_000);
+			for _ in 0..4 {
+				assert_ok!(KittyModule::create(Origin::signed(1)));
+			}
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(10)));
+			let nonce = KittyModule::listing_nonce(0);
+			assert_ok!(KittyModule::lend(Origin::signed(1), 1, 2, 5, 10));
+			assert_ok!(KittyModule::borrow(Origin::signed(2), 1));
+			assert_ok!(KittyModule::request_loan(Origin::signed(1), 2, 100, 10, 5));
+			assert_ok!(KittyModule::fund_loan(Origin::signed(2), 2, 100, 10, 5));
+			assert_ok!(KittyModule::offer_transfer(Origin::signed(1), 2, 3));
+
+			for kitty_id in 0..4 {
+				assert_ok!(KittyModule::force_transfer(Origin::ROOT, 1, 3, kitty_id));
+				assert_eq!(KittyModule::kitty_owner(kitty_id), Some(3));
+				assert_eq!(KittyModule::kitty_lock(kitty_id), None);
+			}
+			assert_eq!(KittyModule::kitty_price(0), None);
+			assert_eq!(KittyModule::listings_page(None, None, 10), vec![]);
+			assert!(KittyModule::listing_nonce(0) != nonce);
+			assert_eq!(KittyModule::kitty_rental(1), None);
+			assert_eq!(KittyModule::kitty_holder(1), Some(3));
+			assert_eq!(KittyModule::kitty_loan(2), None);
+			assert_eq!(KittyModule::transfer_offer(3), None);
+
+			// The ended rental, loan and offer leave the kitties alone when they fall due
+			KittyModule::on_finalize(5);
+			for kitty_id in 0..4 {
+				assert_eq!(KittyModule::kitty_owner(kitty_id), Some(3));
+			}
+			assert_ok!(KittyModule::transfer(Origin::signed(3), 1, 2));
+		});
+	}
+
+	#[test]
+	fn root_can_delist_and_move_kitties() {
+		run_test(|| {
+			assert_ok!(KittyModule::create(Origin::signed(1)));
+			assert_ok!(KittyModule::ask(Origin::signed(1), 0, Some(10)));
+			let nonce = KittyModule::listing_nonce(0);
+
+			assert_noop!(KittyModule::force_delist(Origin::signed(1), 0), Error::BadOrigin);
+			assert_ok!(KittyModule::force_delist(Origin::ROOT, 0));
+			assert_noop!(KittyModule::force_delist(Origin::ROOT, 0), Error::NotForSale);
+			assert_eq!(KittyModule::kitty_price(0), None);
+			assert_eq!(KittyModule::listings_page(None, None, 10), vec![]);
+			assert!(KittyModule::listing_nonce(0) != nonce);
+
+			// Pauses and block lists do not apply to root
+			assert_ok!(KittyModule::set_paused(Origin::ROOT, Activity::Trading, true));
+			assert_ok!(KittyModule::set_blocked(Origin::ROOT, 2, true));
+			assert_noop!(KittyModule::force_transfer(Origin::ROOT, 2, 3, 0), Error::NotOwner);
+			assert_ok!(KittyModule::force_transfer(Origin::ROOT, 1, 2, 0));
+			assert_eq!(KittyModule::kitty_owner(0), Some(2));
+		});
+	}
 }
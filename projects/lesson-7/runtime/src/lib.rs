@@ -9,14 +9,17 @@
 include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
 
 use rstd::prelude::*;
+use codec::{Encode, Decode};
+use runtime_io::blake2_128;
 use primitives::{OpaqueMetadata, crypto::key_types};
 use sr_primitives::{
 	ApplyResult, transaction_validity::TransactionValidity, generic, create_runtime_str,
 	impl_opaque_keys, AnySignature
 };
-use sr_primitives::traits::{NumberFor, BlakeTwo256, Block as BlockT, DigestFor, StaticLookup, Verify, ConvertInto};
+use sr_primitives::traits::{NumberFor, BlakeTwo256, Block as BlockT, DigestFor, StaticLookup, Verify, ConvertInto, SaturatedConversion};
 use sr_primitives::weights::Weight;
 use babe::{AuthorityId as BabeId};
+use babe_primitives::{BABE_ENGINE_ID, RawBabePreDigest, VRF_OUTPUT_LENGTH};
 use grandpa::{AuthorityId as GrandpaId, AuthorityWeight as GrandpaWeight};
 use grandpa::fg_primitives::{self, ScheduledChange};
 use client::{
@@ -33,7 +36,7 @@ pub use sr_primitives::BuildStorage;
 pub use timestamp::Call as TimestampCall;
 pub use balances::Call as BalancesCall;
 pub use sr_primitives::{Permill, Perbill};
-pub use support::{StorageValue, construct_runtime, parameter_types};
+pub use support::{StorageValue, construct_runtime, parameter_types, traits::Get};
 
 /// An index to a block.
 pub type BlockNumber = u32;
@@ -65,10 +68,16 @@ pub type DigestItem = generic::DigestItem<Hash>;
 mod template;
 
 /// Used for the module kitties in `./kitties.rs`
-mod kitties;
+pub mod kitties;
 
 mod linked_item;
 
+/// Genesis consensus timing in `./chain_params.rs`
+pub mod chain_params;
+
+/// Validators chosen by root in `./validator_set.rs`
+pub mod validator_set;
+
 /// Opaque types. These are used by the CLI to instantiate machinery that don't need to know
 /// the specifics of the runtime. They can then be made to be agnostic over specific formats
 /// of data like extrinsics, allowing for them to continue syncing the network through upgrades
@@ -97,13 +106,14 @@ pub mod opaque {
 	}
 }
 
-/// This runtime version.
+/// This runtime version. `spec_version` goes up with every change to runtime behaviour, or
+/// nodes with an older native runtime keep executing it in place of the upgraded wasm.
 pub const VERSION: RuntimeVersion = RuntimeVersion {
 	spec_name: create_runtime_str!("substrate-kitties"),
 	impl_name: create_runtime_str!("substrate-kitties"),
 	authoring_version: 3,
-	spec_version: 4,
-	impl_version: 4,
+	spec_version: 5,
+	impl_version: 5,
 	apis: RUNTIME_API_VERSIONS,
 };
 
@@ -117,9 +127,9 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 /// of `c` configured for BABE (where `1 - c` represents the probability of
 /// a slot being empty).
 /// This value is only used indirectly to define the unit constants below
-/// that are expressed in blocks. The rest of the code should use
-/// `SLOT_DURATION` instead (like the timestamp module for calculating the
-/// minimum period).
+/// that are expressed in blocks, and as the default slot duration of the
+/// genesis config. The rest of the code should use `ChainParams::slot_duration`
+/// instead (like the timestamp module for calculating the minimum period).
 /// <https://research.web3.foundation/en/latest/polkadot/BABE/Babe/#6-practical-results>
 pub const MILLISECS_PER_BLOCK: u64 = 6000;
 
@@ -135,6 +145,14 @@ pub const DAYS: BlockNumber = HOURS * 24;
 // 1 in 4 blocks (on average, not counting collisions) will be primary babe blocks.
 pub const PRIMARY_PROBABILITY: (u64, u64) = (1, 4);
 
+// How balances and addresses are displayed, advertised to wallets in the chain spec properties.
+pub const TOKEN_SYMBOL: &str = "KTY";
+pub const TOKEN_DECIMALS: u32 = 12;
+pub const SS58_FORMAT: u8 = 42;
+
+// One whole token in the smallest `Balance` unit, 10^TOKEN_DECIMALS.
+pub const DOLLARS: Balance = 1_000_000_000_000;
+
 /// The version infromation used to identify this runtime when compiled natively.
 #[cfg(feature = "std")]
 pub fn native_version() -> NativeVersion {
@@ -186,9 +204,22 @@ impl system::Trait for Runtime {
 	type Version = Version;
 }
 
-parameter_types! {
-	pub const EpochDuration: u64 = EPOCH_DURATION_IN_BLOCKS as u64;
-	pub const ExpectedBlockTime: u64 = MILLISECS_PER_BLOCK;
+/// BABE slots per epoch, from the genesis config.
+pub struct EpochDuration;
+
+impl Get<u64> for EpochDuration {
+	fn get() -> u64 {
+		ChainParams::epoch_duration()
+	}
+}
+
+/// Milliseconds per slot, from the genesis config.
+pub struct ExpectedBlockTime;
+
+impl Get<u64> for ExpectedBlockTime {
+	fn get() -> u64 {
+		ChainParams::slot_duration()
+	}
 }
 
 impl babe::Trait for Runtime {
@@ -212,8 +243,54 @@ impl indices::Trait for Runtime {
 	type Event = Event;
 }
 
-parameter_types! {
-	pub const MinimumPeriod: u64 = 1000;
+/// Half the slot duration from the genesis config. BABE derives its slot duration from the
+/// timestamp module as twice the minimum period.
+pub struct MinimumPeriod;
+
+impl Get<u64> for MinimumPeriod {
+	fn get() -> u64 {
+		ChainParams::slot_duration() / 2
+	}
+}
+
+impl chain_params::Trait for Runtime {}
+
+/// The BABE and GRANDPA authorities of a chain that ran without the session module, each
+/// identified by the account of its BABE key. Both lists were set from the same genesis
+/// authorities, so they are in the same order.
+pub struct ExistingAuthorities;
+
+impl Get<Vec<(AccountId, opaque::SessionKeys)>> for ExistingAuthorities {
+	fn get() -> Vec<(AccountId, opaque::SessionKeys)> {
+		Babe::authorities().into_iter()
+			.zip(Grandpa::grandpa_authorities())
+			.filter_map(|((babe, _), (grandpa, _))| {
+				let account = AccountId::decode(&mut &babe.encode()[..]).ok()?;
+				Some((account, opaque::SessionKeys { grandpa, babe }))
+			})
+			.collect()
+	}
+}
+
+impl validator_set::Trait for Runtime {
+	type Event = Event;
+	type ExistingValidators = ExistingAuthorities;
+}
+
+impl session::Trait for Runtime {
+	type Event = Event;
+	/// Sessions follow BABE epochs.
+	type ShouldEndSession = Babe;
+	/// The validator set module hands over its validators when they changed.
+	type OnSessionEnding = ValidatorSet;
+	/// Passes the keys of each validator on to GRANDPA and BABE, in the order of `SessionKeys`.
+	type SessionHandler = opaque::SessionHandlers;
+	type Keys = opaque::SessionKeys;
+	/// Validators are identified by their stash account.
+	type ValidatorId = AccountId;
+	/// Session keys are set by the controller of the stash.
+	type ValidatorIdOf = validator_set::StashOf<Self>;
+	type SelectInitialValidators = ValidatorSet;
 }
 
 impl timestamp::Trait for Runtime {
@@ -261,10 +338,110 @@ impl template::Trait for Runtime {
 	type Event = Event;
 }
 
+/// Milliseconds in a day.
+const DAY_IN_MILLISECS: u64 = 24 * 60 * 60 * 1000;
+
+/// Blocks in `millisecs` at the slot duration from the genesis config, counting a block
+/// for every slot like `DAYS` does.
+fn blocks_in(millisecs: u64) -> BlockNumber {
+	(millisecs / ChainParams::slot_duration()).saturated_into()
+}
+
+/// Two epochs from the genesis config. BABE randomness for the current and the next epoch is
+/// already known, so a reveal has to land two epochs after the commit for the committer not
+/// to know it.
+pub struct MinRevealDelay;
+
+impl Get<BlockNumber> for MinRevealDelay {
+	fn get() -> BlockNumber {
+		(2 * ChainParams::epoch_duration()).saturated_into()
+	}
+}
+
+/// A day of blocks, and at least an epoch past `MinRevealDelay` so that long epochs leave
+/// time to reveal.
+pub struct CommitExpiry;
+
+impl Get<BlockNumber> for CommitExpiry {
+	fn get() -> BlockNumber {
+		let reveal_window = MinRevealDelay::get().saturating_add(ChainParams::epoch_duration().saturated_into());
+		blocks_in(DAY_IN_MILLISECS).max(reveal_window)
+	}
+}
+
+/// A week of blocks at the slot duration from the genesis config.
+pub struct TransferOfferExpiry;
+
+impl Get<BlockNumber> for TransferOfferExpiry {
+	fn get() -> BlockNumber {
+		blocks_in(7 * DAY_IN_MILLISECS)
+	}
+}
+
+/// A year of blocks at the slot duration from the genesis config.
+pub struct MaxLoanDuration;
+
+impl Get<BlockNumber> for MaxLoanDuration {
+	fn get() -> BlockNumber {
+		blocks_in(365 * DAY_IN_MILLISECS)
+	}
+}
+
 impl kitties::Trait for Runtime {
 	type Event = Event;
 	type KittyIndex = u32;
 	type Currency = Balances;
+	type KnownAccounts = IndexedAccounts;
+	type Randomness = BabeRandomness;
+	type MinRevealDelay = MinRevealDelay;
+	type CommitExpiry = CommitExpiry;
+	type TransferOfferExpiry = TransferOfferExpiry;
+	type MaxLoanDuration = MaxLoanDuration;
+}
+
+/// Kitty DNA randomness mixing the BABE randomness of the current epoch with the VRF output
+/// of the block being built.
+///
+/// The epoch randomness is known an epoch ahead, but the VRF output stays with the block
+/// author until the block is published, so a sender can not work out the value their
+/// extrinsic will get. The author can, and may pick which extrinsics to include or withhold
+/// the block altogether. Blocks authored in secondary slots have no VRF output, so their value
+/// follows from the epoch randomness and can be computed in advance. `reveal_create` is not
+/// exposed to either, it fixes the block whose randomness it uses before the salt is revealed.
+pub struct BabeRandomness;
+
+impl BabeRandomness {
+	/// The VRF output in the BABE pre-digest of the block being built, None for a block
+	/// authored in a secondary slot.
+	fn current_vrf_output() -> Option<[u8; VRF_OUTPUT_LENGTH]> {
+		System::digest().logs.iter()
+			.filter_map(|log| log.as_pre_runtime())
+			.filter(|(engine, _)| *engine == BABE_ENGINE_ID)
+			.filter_map(|(_, mut data)| RawBabePreDigest::decode(&mut data).ok())
+			.filter_map(|digest| match digest {
+				RawBabePreDigest::Primary { vrf_output, .. } => Some(vrf_output),
+				RawBabePreDigest::Secondary { .. } => None,
+			})
+			.next()
+	}
+}
+
+impl kitties::Randomness for BabeRandomness {
+	fn random(subject: &[u8]) -> [u8; 16] {
+		(Babe::randomness(), Self::current_vrf_output(), subject).using_encoded(blake2_128)
+	}
+}
+
+/// Enumerates every account registered with the indices module, a batch per enum set.
+pub struct IndexedAccounts;
+
+impl kitties::KnownAccounts<AccountId> for IndexedAccounts {
+	fn known_accounts_batch(batch: u32) -> Vec<AccountId> {
+		if batch > Indices::next_enum_set() {
+			return Vec::new();
+		}
+		Indices::enum_set(batch)
+	}
 }
 
 construct_runtime!(
@@ -283,7 +460,12 @@ construct_runtime!(
 		// Used for the module template in `./template.rs`
 		TemplateModule: template::{Module, Call, Storage, Event<T>},
 		// Substrate Kitties module
-		Kitties: kitties::{Module, Storage, Call, Event<T>},
+		Kitties: kitties::{Module, Storage, Call, Config<T>, Event<T>, Error},
+		// Appended so that the modules before them keep their indexes
+		ChainParams: chain_params::{Module, Storage, Config},
+		// Built and initialized before the session module, which takes its validators from it
+		ValidatorSet: validator_set::{Module, Call, Storage, Config<T>, Event<T>, Error},
+		Session: session::{Module, Call, Storage, Event, Config<T>},
 	}
 );
 
@@ -396,7 +578,7 @@ impl_runtime_apis! {
 			babe_primitives::BabeConfiguration {
 				median_required_blocks: 1000,
 				slot_duration: Babe::slot_duration(),
-				c: PRIMARY_PROBABILITY,
+				c: ChainParams::primary_probability(),
 			}
 		}
 
@@ -406,12 +588,40 @@ impl_runtime_apis! {
 				authorities: Babe::authorities(),
 				epoch_index: Babe::epoch_index(),
 				randomness: Babe::randomness(),
-				duration: EpochDuration::get(),
+				duration: ChainParams::epoch_duration(),
 				secondary_slots: Babe::secondary_slots().0,
 			}
 		}
 	}
 
+	impl kitties::KittiesApi<Block, AccountId, u32, Balance> for Runtime {
+		#[cfg(feature = "std")]
+		fn check_integrity() -> Result<(), Vec<u8>> {
+			Kitties::check_integrity().map_err(|e| e.as_bytes().to_vec())
+		}
+
+		#[cfg(not(feature = "std"))]
+		fn check_integrity() -> Result<(), Vec<u8>> {
+			Err(b"The integrity check is only available in the native runtime".to_vec())
+		}
+
+		fn listings(seller: Option<AccountId>, after: Option<u32>, limit: u32) -> Vec<(u32, Balance)> {
+			Kitties::listings_page(seller, after, limit)
+		}
+
+		fn kitties_count() -> u32 {
+			Kitties::kitties_count()
+		}
+
+		fn kitty(kitty_id: u32) -> Option<kitties::KittyDetails<AccountId, Balance>> {
+			Kitties::kitty_details(kitty_id)
+		}
+
+		fn owned(owner: AccountId, after: Option<u32>, limit: u32) -> Vec<u32> {
+			Kitties::owned_page(owner, after, limit)
+		}
+	}
+
 	impl substrate_session::SessionKeys<Block> for Runtime {
 		fn generate_session_keys(seed: Option<Vec<u8>>) -> Vec<u8> {
 			let seed = seed.as_ref().map(|s| rstd::str::from_utf8(&s).expect("Seed is an utf8 string"));
@@ -1,6 +1,7 @@
 use support::{StorageMap, Parameter};
 use sr_primitives::traits::Member;
 use codec::{Encode, Decode};
+use rstd::prelude::*;
 
 #[cfg_attr(feature = "std", derive(Debug, PartialEq, Eq))]
 #[derive(Encode, Decode)]
@@ -9,6 +10,16 @@ pub struct LinkedItem<Value> {
 	pub next: Option<Value>,
 }
 
+/// Errors returned by `LinkedList` operations.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum LinkedListError {
+	/// The value is already linked into the list.
+	AlreadyPresent,
+	/// The value is not linked into the list.
+	NotFound,
+}
+
 pub struct LinkedList<Storage, Key, Value>(rstd::marker::PhantomData<(Storage, Key, Value)>);
 
 impl<Storage, Key, Value> LinkedList<Storage, Key, Value> where
@@ -35,7 +46,32 @@ impl<Storage, Key, Value> LinkedList<Storage, Key, Value> where
 		Storage::insert(&(key.clone(), value), item);
 	}
 
-	pub fn append(key: &Key, value: Value) {
+	/// Whether `value` is linked into the list stored under `key`.
+	pub fn contains(key: &Key, value: Value) -> bool {
+		Storage::exists(&(key.clone(), Some(value)))
+	}
+
+	/// Up to `limit` values following `after`, or from the start of the list when `after`
+	/// is None. Empty if `after` is not in the list.
+	pub fn page(key: &Key, after: Option<Value>, limit: usize) -> Vec<Value> {
+		let mut values = Vec::new();
+		if after.map(|value| !Self::contains(key, value)).unwrap_or(false) {
+			return values;
+		}
+
+		let mut current = Self::read(key, after).next;
+		while let Some(value) = current.filter(|_| values.len() < limit) {
+			values.push(value);
+			current = Self::read(key, Some(value)).next;
+		}
+		values
+	}
+
+	pub fn append(key: &Key, value: Value) -> Result<(), LinkedListError> {
+		if Self::contains(key, value) {
+			return Err(LinkedListError::AlreadyPresent);
+		}
+
 		let head = Self::read_head(key);
 		let new_head = LinkedItem {
 			prev: Some(value),
@@ -56,25 +92,29 @@ impl<Storage, Key, Value> LinkedList<Storage, Key, Value> where
 			next: None,
 		};
 		Self::write(key, Some(value), item);
+
+		Ok(())
 	}
 
-	pub fn remove(key: &Key, value: Value) {
-		if let Some(item) = Storage::take(&(key.clone(), Some(value))) {
-			let prev = Self::read(key, item.prev);
-			let new_prev = LinkedItem {
-				prev: prev.prev,
-				next: item.next,
-			};
+	pub fn remove(key: &Key, value: Value) -> Result<(), LinkedListError> {
+		let item = Storage::take(&(key.clone(), Some(value))).ok_or(LinkedListError::NotFound)?;
 
-			Self::write(key, item.prev, new_prev);
+		let prev = Self::read(key, item.prev);
+		let new_prev = LinkedItem {
+			prev: prev.prev,
+			next: item.next,
+		};
 
-			let next = Self::read(key, item.next);
-			let new_next = LinkedItem {
-				prev: item.prev,
-				next: next.next,
-			};
+		Self::write(key, item.prev, new_prev);
 
-			Self::write(key, item.next, new_next);
-		}
+		let next = Self::read(key, item.next);
+		let new_next = LinkedItem {
+			prev: item.prev,
+			next: next.next,
+		};
+
+		Self::write(key, item.next, new_next);
+
+		Ok(())
 	}
 }
\ No newline at end of file
@@ -0,0 +1,447 @@
+//! A proof-of-authority validator set. Root adds and removes validators, and the session
+//! module picks up the new set when the current session ends.
+//!
+//! Validators are identified by their stash account. A validator is added together with its
+//! session keys, and later the controller account can change them with `session.set_keys`,
+//! which `StashOf` maps back to the stash.
+//!
+//! A chain that ran BABE and GRANDPA before this module and the session module existed has no
+//! validators stored. The first block after the upgrade takes its current authorities over as
+//! validators, each its own controller, so that the first session rotation keeps them.
+//! `StorageVersion` records that this happened, so it runs once.
+
+use support::{decl_module, decl_storage, decl_event, decl_error, ensure, StorageValue, StorageMap};
+use support::storage::generator;
+use support::traits::{Get, OnFreeBalanceZero};
+use sr_primitives::traits::Convert;
+use sr_primitives::weights::{SimpleDispatchInfo, Weight};
+use system::{ensure_root, RawOrigin};
+use session::{OnSessionEnding, SelectInitialValidators, SessionIndex};
+use rstd::{marker::PhantomData, prelude::*};
+
+/// Weight of the root calls, which are rare and touch a handful of storage items.
+const SET_VALIDATORS_WEIGHT: Weight = 10_000;
+
+/// The storage layout version written by this runtime.
+/// Version 0 is a chain upgraded from one without this module, whose authorities are not
+/// validators yet. Version 1 stores them in `Validators`.
+pub const CURRENT_STORAGE_VERSION: u32 = 1;
+
+/// The module's configuration trait.
+pub trait Trait: system::Trait + session::Trait<ValidatorId = <Self as system::Trait>::AccountId> {
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+	/// The authorities of a chain upgraded from one without this module, with their session
+	/// keys. Only read while no validators are stored.
+	type ExistingValidators: Get<Vec<(Self::AccountId, Self::Keys)>>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as ValidatorSet {
+		/// Stash accounts of the validators, the session after next included
+		pub Validators get(validators): Vec<T::AccountId>;
+		/// The controller of each validator stash
+		pub Controllers get(controller_of): map T::AccountId => Option<T::AccountId>;
+		/// The stash of each validator controller
+		pub Stashes get(stash_of): map T::AccountId => Option<T::AccountId>;
+		/// Whether `Validators` changed since the session module last asked for it
+		Changed: bool;
+		/// The storage layout version, see `CURRENT_STORAGE_VERSION`
+		pub StorageVersion get(storage_version): u32;
+	}
+	add_extra_genesis {
+		/// Initial validators as (stash, controller)
+		config(validators): Vec<(T::AccountId, T::AccountId)>;
+		build(|config: &GenesisConfig<T>| {
+			for (stash, controller) in &config.validators {
+				<Module<T>>::insert_validator(stash, controller)
+					.ok().expect("Genesis validators are unique; qed");
+			}
+			// Genesis storage is written in the current layout
+			StorageVersion::put(CURRENT_STORAGE_VERSION);
+		});
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where <T as system::Trait>::AccountId {
+		/// A validator joins at the next session change. (stash, controller)
+		ValidatorAdded(AccountId, AccountId),
+		/// A validator leaves at the next session change. (stash)
+		ValidatorRemoved(AccountId),
+		/// The authorities of the chain became its validators after an upgrade. (count)
+		ValidatorsMigrated(u32),
+	}
+);
+
+decl_error! {
+	/// Error for the validator set module.
+	pub enum Error {
+		/// The stash is already the stash or controller of a validator
+		AlreadyValidator,
+		/// The controller is already the stash or controller of a validator
+		ControllerInUse,
+		/// The stash is not a validator
+		NotValidator,
+		/// The last validator can not be removed
+		LastValidator,
+		/// The session module refused the keys, for example because another validator uses them
+		InvalidSessionKeys,
+	}
+}
+
+decl_module! {
+	/// The module declaration.
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		type Error = Error;
+
+		fn deposit_event() = default;
+
+		fn on_initialize(_n: T::BlockNumber) {
+			if Self::storage_version() < CURRENT_STORAGE_VERSION {
+				Self::migrate_authorities();
+				StorageVersion::put(CURRENT_STORAGE_VERSION);
+			}
+		}
+
+		/// Make `stash` a validator, controlled by `controller`, with session `keys`. It takes
+		/// part in consensus from the session after next. The keys are set as if `controller`
+		/// sent `session.set_keys`, so a validator can never rotate in without keys.
+		#[weight = SimpleDispatchInfo::FixedOperational(SET_VALIDATORS_WEIGHT)]
+		pub fn add_validator(origin, stash: T::AccountId, controller: T::AccountId, keys: T::Keys) {
+			ensure_root(origin)?;
+
+			Self::insert_validator(&stash, &controller)?;
+			if Self::set_keys(&controller, keys).is_err() {
+				Self::forget_validator(&stash);
+				return Err(Error::InvalidSessionKeys);
+			}
+			Changed::put(true);
+
+			Self::deposit_event(RawEvent::ValidatorAdded(stash, controller));
+		}
+
+		/// Stop `stash` from validating from the session after next, and free its session keys
+		/// for other validators.
+		#[weight = SimpleDispatchInfo::FixedOperational(SET_VALIDATORS_WEIGHT)]
+		pub fn remove_validator(origin, stash: T::AccountId) {
+			ensure_root(origin)?;
+
+			let mut validators = Self::validators();
+			let position = validators.iter().position(|v| *v == stash).ok_or(Error::NotValidator)?;
+			ensure!(validators.len() > 1, Error::LastValidator);
+
+			validators.remove(position);
+			<Validators<T>>::put(validators);
+			Self::forget_validator(&stash);
+			Self::purge_keys(&stash);
+			Changed::put(true);
+
+			Self::deposit_event(RawEvent::ValidatorRemoved(stash));
+		}
+	}
+}
+
+impl<T: Trait> Module<T> {
+	/// Record a validator. Neither account may be the stash or controller of another one,
+	/// so that `StashOf` and `Controllers` stay each other's inverse.
+	fn insert_validator(stash: &T::AccountId, controller: &T::AccountId) -> Result<(), Error> {
+		ensure!(!Self::is_in_use(stash), Error::AlreadyValidator);
+		ensure!(!Self::is_in_use(controller), Error::ControllerInUse);
+
+		<Validators<T>>::mutate(|validators| validators.push(stash.clone()));
+		<Controllers<T>>::insert(stash, controller);
+		<Stashes<T>>::insert(controller, stash);
+		Ok(())
+	}
+
+	/// Whether `account` is the stash or controller of a validator.
+	fn is_in_use(account: &T::AccountId) -> bool {
+		<Controllers<T>>::exists(account) || <Stashes<T>>::exists(account)
+	}
+
+	/// Undo `insert_validator`.
+	fn forget_validator(stash: &T::AccountId) {
+		<Validators<T>>::mutate(|validators| validators.retain(|v| v != stash));
+		if let Some(controller) = <Controllers<T>>::take(stash) {
+			<Stashes<T>>::remove(&controller);
+		}
+	}
+
+	/// Set the session keys of the validator controlled by `controller`.
+	fn set_keys(controller: &T::AccountId, keys: T::Keys) -> Result<(), &'static str> {
+		<session::Module<T>>::set_keys(RawOrigin::Signed(controller.clone()).into(), keys, Vec::new())
+	}
+
+	/// Drop the session keys of `stash` and release their key owner entries. The session
+	/// module does this for accounts that are reaped and has no call for it.
+	fn purge_keys(stash: &T::AccountId) {
+		<session::Module<T> as OnFreeBalanceZero<T::AccountId>>::on_free_balance_zero(stash);
+	}
+
+	/// Take the BABE and GRANDPA authorities over as validators, each its own controller.
+	/// The session module only reads its queued keys and validators when a session ends, and
+	/// has no calls to set them, so they are written through `SessionValidators` and
+	/// `SessionQueuedKeys` as its genesis would have.
+	fn migrate_authorities() {
+		let mut queued = Vec::new();
+		for (validator, keys) in T::ExistingValidators::get() {
+			if Self::insert_validator(&validator, &validator).is_err() {
+				continue;
+			}
+			if Self::set_keys(&validator, keys.clone()).is_err() {
+				Self::forget_validator(&validator);
+				continue;
+			}
+			queued.push((validator, keys));
+		}
+		if queued.is_empty() {
+			return;
+		}
+
+		let validators: Vec<_> = queued.iter().map(|(validator, _)| validator.clone()).collect();
+		<SessionValidators<T>>::put(&validators);
+		<SessionQueuedKeys<T>>::put(&queued);
+		Self::deposit_event(RawEvent::ValidatorsMigrated(queued.len() as u32));
+	}
+}
+
+impl<T: Trait> OnSessionEnding<T::AccountId> for Module<T> {
+	fn on_session_ending(_ending: SessionIndex, _start: SessionIndex) -> Option<Vec<T::AccountId>> {
+		if Changed::take() {
+			Some(Self::validators())
+		} else {
+			None
+		}
+	}
+}
+
+impl<T: Trait> SelectInitialValidators<T::AccountId> for Module<T> {
+	fn select_initial_validators() -> Option<Vec<T::AccountId>> {
+		Some(Self::validators())
+	}
+}
+
+/// The session module's `Validators`, which it only offers a getter for.
+struct SessionValidators<T>(PhantomData<T>);
+
+impl<T: Trait> generator::StorageValue<Vec<T::AccountId>> for SessionValidators<T> {
+	type Query = Vec<T::AccountId>;
+
+	fn unhashed_key() -> &'static [u8] {
+		b"Session Validators"
+	}
+
+	fn from_optional_value_to_query(v: Option<Vec<T::AccountId>>) -> Self::Query {
+		v.unwrap_or_default()
+	}
+
+	fn from_query_to_optional_value(v: Self::Query) -> Option<Vec<T::AccountId>> {
+		Some(v)
+	}
+}
+
+/// The session module's `QueuedKeys`, which it only offers a getter for.
+struct SessionQueuedKeys<T>(PhantomData<T>);
+
+impl<T: Trait> generator::StorageValue<Vec<(T::AccountId, T::Keys)>> for SessionQueuedKeys<T> {
+	type Query = Vec<(T::AccountId, T::Keys)>;
+
+	fn unhashed_key() -> &'static [u8] {
+		b"Session QueuedKeys"
+	}
+
+	fn from_optional_value_to_query(v: Option<Vec<(T::AccountId, T::Keys)>>) -> Self::Query {
+		v.unwrap_or_default()
+	}
+
+	fn from_query_to_optional_value(v: Self::Query) -> Option<Vec<(T::AccountId, T::Keys)>> {
+		Some(v)
+	}
+}
+
+/// Maps a controller to the stash it controls, so that `session.set_keys` sent by a controller
+/// sets the keys of its stash.
+pub struct StashOf<T>(PhantomData<T>);
+
+impl<T: Trait> Convert<T::AccountId, Option<T::AccountId>> for StashOf<T> {
+	fn convert(controller: T::AccountId) -> Option<T::AccountId> {
+		<Module<T>>::stash_of(&controller)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use runtime_io::with_externalities;
+	use primitives::{H256, Blake2Hasher};
+	use support::{impl_outer_origin, assert_ok, assert_noop, parameter_types};
+	use sr_primitives::{traits::{BlakeTwo256, IdentityLookup, OnInitialize}, testing::{Header, UintAuthorityId}};
+	use sr_primitives::weights::Weight;
+	use sr_primitives::Perbill;
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq, Debug)]
+	pub struct Test;
+	parameter_types! {
+		pub const BlockHashCount: u64 = 250;
+		pub const MaximumBlockWeight: Weight = 1024;
+		pub const MaximumBlockLength: u32 = 2 * 1024;
+		pub const AvailableBlockRatio: Perbill = Perbill::from_percent(75);
+	}
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Call = ();
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type WeightMultiplierUpdate = ();
+		type Event = ();
+		type BlockHashCount = BlockHashCount;
+		type MaximumBlockWeight = MaximumBlockWeight;
+		type MaximumBlockLength = MaximumBlockLength;
+		type AvailableBlockRatio = AvailableBlockRatio;
+		type Version = ();
+	}
+	parameter_types! {
+		pub const Period: u64 = 1;
+		pub const Offset: u64 = 0;
+	}
+	impl session::Trait for Test {
+		type Event = ();
+		type ShouldEndSession = session::PeriodicSessions<Period, Offset>;
+		type OnSessionEnding = ValidatorSet;
+		type SessionHandler = ();
+		type Keys = UintAuthorityId;
+		type ValidatorId = u64;
+		type ValidatorIdOf = StashOf<Test>;
+		type SelectInitialValidators = ValidatorSet;
+	}
+	/// Authorities 1 and 2 of a chain that ran without the module.
+	pub struct ExistingValidators;
+	impl Get<Vec<(u64, UintAuthorityId)>> for ExistingValidators {
+		fn get() -> Vec<(u64, UintAuthorityId)> {
+			vec![(1, UintAuthorityId(1)), (2, UintAuthorityId(2))]
+		}
+	}
+	impl Trait for Test {
+		type Event = ();
+		type ExistingValidators = ExistingValidators;
+	}
+	type ValidatorSet = Module<Test>;
+	type Session = session::Module<Test>;
+
+	fn empty_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		system::GenesisConfig::default().build_storage::<Test>().unwrap().into()
+	}
+
+	// Validators 1 and 2, controlled by 11 and 12, with keys 1 and 2.
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut ext = empty_test_ext();
+		with_externalities(&mut ext, || {
+			assert_ok!(ValidatorSet::insert_validator(&1, &11));
+			assert_ok!(ValidatorSet::insert_validator(&2, &12));
+			assert_ok!(ValidatorSet::set_keys(&11, UintAuthorityId(1)));
+			assert_ok!(ValidatorSet::set_keys(&12, UintAuthorityId(2)));
+		});
+		ext
+	}
+
+	#[test]
+	fn root_adds_and_removes_validators() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_eq!(ValidatorSet::select_initial_validators(), Some(vec![1, 2]));
+			assert_noop!(ValidatorSet::add_validator(Origin::signed(1), 3, 13, UintAuthorityId(3)), Error::BadOrigin);
+
+			assert_ok!(ValidatorSet::add_validator(Origin::ROOT, 3, 13, UintAuthorityId(3)));
+			assert_eq!(ValidatorSet::validators(), vec![1, 2, 3]);
+			assert_eq!(StashOf::<Test>::convert(13), Some(3));
+
+			// The new validator is queued with the keys it was added with
+			Session::rotate_session();
+			assert!(Session::queued_keys().contains(&(3, UintAuthorityId(3))));
+
+			assert_ok!(ValidatorSet::remove_validator(Origin::ROOT, 1));
+			assert_eq!(ValidatorSet::validators(), vec![2, 3]);
+			assert_eq!(StashOf::<Test>::convert(11), None);
+			assert_eq!(ValidatorSet::controller_of(1), None);
+
+			// The keys of the removed validator are free for a new one
+			assert_ok!(ValidatorSet::add_validator(Origin::ROOT, 4, 14, UintAuthorityId(1)));
+		});
+	}
+
+	#[test]
+	fn validators_and_controllers_are_unique() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(ValidatorSet::add_validator(Origin::ROOT, 1, 13, UintAuthorityId(3)), Error::AlreadyValidator);
+			assert_noop!(ValidatorSet::add_validator(Origin::ROOT, 3, 11, UintAuthorityId(3)), Error::ControllerInUse);
+			assert_noop!(ValidatorSet::add_validator(Origin::ROOT, 3, 13, UintAuthorityId(1)), Error::InvalidSessionKeys);
+			// Neither account may take the other role of an existing validator
+			assert_noop!(ValidatorSet::add_validator(Origin::ROOT, 11, 13, UintAuthorityId(3)), Error::AlreadyValidator);
+			assert_noop!(ValidatorSet::add_validator(Origin::ROOT, 3, 1, UintAuthorityId(3)), Error::ControllerInUse);
+			assert_eq!(StashOf::<Test>::convert(13), None);
+			assert_noop!(ValidatorSet::remove_validator(Origin::ROOT, 3), Error::NotValidator);
+
+			assert_ok!(ValidatorSet::remove_validator(Origin::ROOT, 1));
+			assert_noop!(ValidatorSet::remove_validator(Origin::ROOT, 2), Error::LastValidator);
+		});
+	}
+
+	#[test]
+	fn sessions_get_the_new_set_once() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_eq!(ValidatorSet::on_session_ending(0, 2), None);
+
+			assert_ok!(ValidatorSet::add_validator(Origin::ROOT, 3, 13, UintAuthorityId(3)));
+			assert_ok!(ValidatorSet::remove_validator(Origin::ROOT, 2));
+			assert_eq!(ValidatorSet::on_session_ending(1, 3), Some(vec![1, 3]));
+			assert_eq!(ValidatorSet::on_session_ending(2, 4), None);
+		});
+	}
+
+	#[test]
+	fn authorities_of_an_upgraded_chain_become_validators() {
+		with_externalities(&mut empty_test_ext(), || {
+			ValidatorSet::on_initialize(1);
+
+			assert_eq!(ValidatorSet::validators(), vec![1, 2]);
+			assert_eq!(StashOf::<Test>::convert(1), Some(1));
+			let queued = vec![(1, UintAuthorityId(1)), (2, UintAuthorityId(2))];
+			assert_eq!(Session::queued_keys(), queued);
+			assert_eq!(Session::validators(), vec![1, 2]);
+
+			// The first rotations keep them instead of queueing an empty set
+			Session::rotate_session();
+			Session::rotate_session();
+			assert_eq!(Session::validators(), vec![1, 2]);
+			assert_eq!(Session::queued_keys(), queued);
+
+			// The migration runs once, even if it found no authorities to take over
+			assert_eq!(ValidatorSet::storage_version(), CURRENT_STORAGE_VERSION);
+			assert_ok!(ValidatorSet::remove_validator(Origin::ROOT, 2));
+			ValidatorSet::on_initialize(2);
+			assert_eq!(ValidatorSet::validators(), vec![1]);
+		});
+	}
+
+	#[test]
+	fn new_chains_do_not_migrate() {
+		let mut ext: runtime_io::TestExternalities<Blake2Hasher> = GenesisConfig::<Test> {
+			validators: vec![(3, 13)],
+		}.build_storage().unwrap().into();
+		with_externalities(&mut ext, || {
+			assert_eq!(ValidatorSet::storage_version(), CURRENT_STORAGE_VERSION);
+			ValidatorSet::on_initialize(1);
+			assert_eq!(ValidatorSet::validators(), vec![3]);
+			assert_eq!(ValidatorSet::controller_of(1), None);
+		});
+	}
+}
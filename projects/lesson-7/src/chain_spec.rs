@@ -1,15 +1,23 @@
 use primitives::{Pair, Public};
 use substrate_kitties_runtime::{
-	AccountId, BabeConfig, BalancesConfig, GenesisConfig, GrandpaConfig,
-	SudoConfig, IndicesConfig, SystemConfig, WASM_BINARY, 
+	AccountId, Balance, BabeConfig, BalancesConfig, ChainParamsConfig, GenesisConfig, GrandpaConfig, KittiesConfig,
+	SessionConfig, SudoConfig, IndicesConfig, SystemConfig, ValidatorSetConfig, WASM_BINARY, TOKEN_SYMBOL, TOKEN_DECIMALS, SS58_FORMAT,
+	DOLLARS, MILLISECS_PER_BLOCK, EPOCH_DURATION_IN_BLOCKS, PRIMARY_PROBABILITY,
 };
+use substrate_kitties_runtime::opaque::SessionKeys;
 use babe_primitives::{AuthorityId as BabeId};
 use grandpa_primitives::{AuthorityId as GrandpaId};
-use substrate_service;
+use substrate_service::{self, Properties};
+use serde::Deserialize;
+use std::{fs, path::PathBuf};
+use crate::snapshot::KittiesSnapshot;
 
 // Note this is the URL for the telemetry server
 //const STAGING_TELEMETRY_URL: &str = "wss://telemetry.polkadot.io/submit/";
 
+/// The initial balance of every endowed account.
+const ENDOWMENT: Balance = 1_000_000 * DOLLARS;
+
 /// Specialized `ChainSpec`. This is a specialization of the general Substrate ChainSpec type.
 pub type ChainSpec = substrate_service::ChainSpec<GenesisConfig>;
 
@@ -22,6 +30,183 @@ pub enum Alternative {
 	Development,
 	/// Whatever the current runtime is, with simple Alice/Bob auths.
 	LocalTestnet,
+	/// Whatever the current runtime is, with the network parameters from a `StagingConfig` file.
+	Staging(PathBuf),
+}
+
+/// Network parameters of a team testnet, read from a JSON file. Keys are SS58 addresses.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct StagingConfig {
+	/// Human readable name of the network
+	pub name: String,
+	/// Identifier of the network, also used for its database directory
+	pub id: String,
+	/// Initial validators
+	pub authorities: Vec<StagingAuthority>,
+	/// The sudo key
+	pub sudo: AccountId,
+	/// Accounts given an initial balance
+	pub endowed_accounts: Vec<AccountId>,
+	/// Multiaddresses of the nodes to connect to on start
+	#[serde(default)]
+	pub boot_nodes: Vec<String>,
+	/// Ticker of the native token, `KTY` if not set
+	#[serde(default)]
+	pub token_symbol: Option<String>,
+	/// Decimal places of balances, 12 if not set
+	#[serde(default)]
+	pub token_decimals: Option<u32>,
+	/// SS58 address format, 42 if not set
+	#[serde(default)]
+	pub ss58_format: Option<u8>,
+	/// Block time and epoch length, the runtime defaults if not set
+	#[serde(default)]
+	pub timing: ConsensusTiming,
+	/// GRANDPA settings, the defaults if not set
+	#[serde(default)]
+	pub finality: FinalitySettings,
+	/// A snapshot written by `kitties export` whose kitties exist from genesis
+	#[serde(default)]
+	pub kitties_snapshot: Option<PathBuf>,
+}
+
+/// Block time and epoch length of a network, written to its genesis config.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ConsensusTiming {
+	/// Milliseconds per BABE slot
+	pub slot_duration: u64,
+	/// BABE slots per epoch
+	pub epoch_duration: u64,
+	/// BABE `c`, where `1 - c` is the probability of a slot without a primary author
+	pub primary_probability: (u64, u64),
+}
+
+impl Default for ConsensusTiming {
+	fn default() -> Self {
+		ConsensusTiming {
+			slot_duration: MILLISECS_PER_BLOCK,
+			epoch_duration: EPOCH_DURATION_IN_BLOCKS as u64,
+			primary_probability: PRIMARY_PROBABILITY,
+		}
+	}
+}
+
+impl ConsensusTiming {
+	/// Check what the `chain_params` genesis config asserts, so that a bad config is an error
+	/// rather than a panic while building genesis.
+	pub fn validate(&self) -> Result<(), String> {
+		if self.slot_duration == 0 || self.slot_duration % 2 != 0 {
+			return Err(format!("Slot duration must be even and non-zero, got {}", self.slot_duration));
+		}
+		if self.epoch_duration == 0 {
+			return Err("Epoch duration must be non-zero".into());
+		}
+		let (numerator, denominator) = self.primary_probability;
+		if denominator == 0 || numerator > denominator {
+			return Err(format!("Primary probability must be between 0 and 1, got {}/{}", numerator, denominator));
+		}
+		Ok(())
+	}
+
+	/// Short slots and epochs for a development chain.
+	pub fn development() -> Self {
+		ConsensusTiming {
+			slot_duration: 2000,
+			epoch_duration: 100,
+			..Default::default()
+		}
+	}
+}
+
+/// GRANDPA settings of a network, carried in its chain spec properties because they are
+/// only used by the node.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FinalitySettings {
+	/// Milliseconds between gossip rounds
+	pub gossip_duration: u64,
+	/// Blocks between justifications kept for light clients
+	pub justification_period: u32,
+}
+
+impl Default for FinalitySettings {
+	fn default() -> Self {
+		FinalitySettings {
+			gossip_duration: 333,
+			justification_period: 4096,
+		}
+	}
+}
+
+impl FinalitySettings {
+	/// Frequent justifications for a development chain.
+	pub fn development() -> Self {
+		FinalitySettings {
+			justification_period: 512,
+			..Default::default()
+		}
+	}
+
+	/// Read the settings from chain spec properties, falling back to the defaults for
+	/// specs written before they were added.
+	pub fn from_properties(properties: &Properties) -> Self {
+		let default = Self::default();
+		FinalitySettings {
+			gossip_duration: properties.get("grandpaGossipDuration")
+				.and_then(|v| v.as_u64())
+				.unwrap_or(default.gossip_duration),
+			justification_period: properties.get("grandpaJustificationPeriod")
+				.and_then(|v| v.as_u64())
+				.map(|v| v as u32)
+				.unwrap_or(default.justification_period),
+		}
+	}
+}
+
+/// Keys of an initial validator in a `StagingConfig`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StagingAuthority {
+	/// Stash account, which identifies the validator
+	pub stash: AccountId,
+	/// Controller account, which sets the session keys of the stash
+	pub controller: AccountId,
+	/// GRANDPA session key
+	pub grandpa: GrandpaId,
+	/// BABE session key
+	pub babe: BabeId,
+}
+
+impl StagingConfig {
+	/// Read the parameters from a JSON file.
+	pub fn from_json_file(path: &PathBuf) -> Result<Self, String> {
+		let json = fs::read_to_string(path)
+			.map_err(|e| format!("Error opening staging config {}: {}", path.display(), e))?;
+		Self::from_json(&json, &path.display().to_string())
+	}
+
+	/// Parse the parameters from JSON, naming `source` in errors.
+	pub fn from_json(json: &str, source: &str) -> Result<Self, String> {
+		let config: Self = serde_json::from_str(json)
+			.map_err(|e| format!("Error parsing staging config {}: {}", source, e))?;
+		config.timing.validate()
+			.map_err(|e| format!("Invalid timing in staging config {}: {}", source, e))?;
+		Ok(config)
+	}
+}
+
+/// Chain spec properties telling wallets how to display balances and addresses, and the
+/// node how to run GRANDPA.
+pub fn chain_properties(token_symbol: &str, token_decimals: u32, ss58_format: u8, finality: FinalitySettings) -> Properties {
+	let mut properties = Properties::new();
+	properties.insert("tokenSymbol".into(), token_symbol.into());
+	properties.insert("tokenDecimals".into(), token_decimals.into());
+	properties.insert("ss58Format".into(), ss58_format.into());
+	properties.insert("grandpaGossipDuration".into(), finality.gossip_duration.into());
+	properties.insert("grandpaJustificationPeriod".into(), finality.justification_period.into());
+	properties
 }
 
 /// Helper function to generate a crypto pair from seed
@@ -66,12 +251,14 @@ impl Alternative {
 					get_from_seed::<AccountId>("Eve//stash"),
 					get_from_seed::<AccountId>("Ferdie//stash"),
 				],
-				true),
+				true,
+				ConsensusTiming::development(),
+				vec![]),
 				vec![],
 				None,
 				None,
 				None,
-				None
+				Some(chain_properties(TOKEN_SYMBOL, TOKEN_DECIMALS, SS58_FORMAT, FinalitySettings::development()))
 			),
 			Alternative::LocalTestnet => ChainSpec::from_genesis(
 				"Local Testnet",
@@ -95,30 +282,78 @@ impl Alternative {
 					get_from_seed::<AccountId>("Eve//stash"),
 					get_from_seed::<AccountId>("Ferdie//stash"),
 				],
-				true),
+				true,
+				ConsensusTiming::default(),
+				vec![]),
 				vec![],
 				None,
 				None,
 				None,
-				None
+				Some(chain_properties(TOKEN_SYMBOL, TOKEN_DECIMALS, SS58_FORMAT, FinalitySettings::default()))
 			),
+			Alternative::Staging(path) => {
+				let config = StagingConfig::from_json_file(&path)?;
+				let boot_nodes = config.boot_nodes.clone();
+				let properties = chain_properties(
+					config.token_symbol.as_ref().map(String::as_str).unwrap_or(TOKEN_SYMBOL),
+					config.token_decimals.unwrap_or(TOKEN_DECIMALS),
+					config.ss58_format.unwrap_or(SS58_FORMAT),
+					config.finality,
+				);
+				let kitties = match config.kitties_snapshot {
+					Some(ref path) => KittiesSnapshot::from_json_file(path)?.into_genesis()?,
+					None => Vec::new(),
+				};
+				ChainSpec::from_genesis(
+					&config.name.clone(),
+					&config.id.clone(),
+					move || testnet_genesis(
+						config.authorities.iter()
+							.map(|a| (a.stash.clone(), a.controller.clone(), a.grandpa.clone(), a.babe.clone()))
+							.collect(),
+						config.sudo.clone(),
+						config.endowed_accounts.clone(),
+						false,
+						config.timing,
+						kitties.clone(),
+					),
+					boot_nodes,
+					None,
+					None,
+					None,
+					Some(properties)
+				)
+			},
 		})
 	}
 
-	pub(crate) fn from(s: &str) -> Option<Self> {
-		match s {
+	/// `staging:<path>` reads the network parameters from the given file. A bare `staging` is
+	/// an error rather than a preset, since any keys built into the node are public.
+	pub(crate) fn from(s: &str) -> Result<Option<Self>, String> {
+		Ok(match s {
 			"dev" => Some(Alternative::Development),
 			"" | "local" => Some(Alternative::LocalTestnet),
+			"staging" => return Err(
+				"The staging chain needs its own keys, use --chain=staging:<path> with a config like res/staging.example.json".into()
+			),
+			s if s.starts_with("staging:") => Some(Alternative::Staging(PathBuf::from(&s["staging:".len()..]))),
 			_ => None,
-		}
+		})
 	}
 }
 
 fn testnet_genesis(initial_authorities: Vec<(AccountId, AccountId, GrandpaId, BabeId)>,
 	root_key: AccountId, 
 	endowed_accounts: Vec<AccountId>,
-	_enable_println: bool) -> GenesisConfig {
+	_enable_println: bool,
+	timing: ConsensusTiming,
+	kitties: Vec<(AccountId, [u8; 16], Option<Balance>)>) -> GenesisConfig {
 	GenesisConfig {
+		chain_params: Some(ChainParamsConfig {
+			slot_duration: timing.slot_duration,
+			epoch_duration: timing.epoch_duration,
+			primary_probability: timing.primary_probability,
+		}),
 		system: Some(SystemConfig {
 			code: WASM_BINARY.to_vec(),
 			changes_trie_config: Default::default(),
@@ -127,17 +362,104 @@ fn testnet_genesis(initial_authorities: Vec<(AccountId, AccountId, GrandpaId, Ba
 			ids: endowed_accounts.clone(),
 		}),
 		balances: Some(BalancesConfig {
-			balances: endowed_accounts.iter().cloned().map(|k|(k, 1 << 60)).collect(),
+			balances: endowed_accounts.iter().cloned().map(|k|(k, ENDOWMENT)).collect(),
 			vesting: vec![],
 		}),
 		sudo: Some(SudoConfig {
 			key: root_key,
 		}),
+		// The session module gives BABE and GRANDPA the keys of the initial validators
 		babe: Some(BabeConfig {
-			authorities: initial_authorities.iter().map(|x| (x.3.clone(), 1)).collect(),
+			authorities: vec![],
 		}),
 		grandpa: Some(GrandpaConfig {
-			authorities: initial_authorities.iter().map(|x| (x.2.clone(), 1)).collect(),
+			authorities: vec![],
+		}),
+		validator_set: Some(ValidatorSetConfig {
+			validators: initial_authorities.iter().map(|x| (x.0.clone(), x.1.clone())).collect(),
+		}),
+		session: Some(SessionConfig {
+			keys: initial_authorities.iter().map(|x| {
+				(x.0.clone(), SessionKeys { grandpa: x.2.clone(), babe: x.3.clone() })
+			}).collect(),
 		}),
+		kitties: Some(KittiesConfig {
+			kitties,
+		}),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tempdir::TempDir;
+
+	/// The example config, which uses the development keys.
+	const EXAMPLE_STAGING_CONFIG: &str = include_str!("../res/staging.example.json");
+
+	#[test]
+	fn example_staging_config_parses() {
+		let config = StagingConfig::from_json(EXAMPLE_STAGING_CONFIG, "res/staging.example.json").unwrap();
+		assert_eq!(config.id, "kitties_staging");
+		assert_eq!(config.authorities.len(), 2);
+		assert_eq!(config.endowed_accounts.len(), 4);
+		assert!(config.kitties_snapshot.is_none());
+	}
+
+	#[test]
+	fn bad_staging_configs_are_rejected() {
+		let sudo = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
+		let bad_address = EXAMPLE_STAGING_CONFIG.replacen(sudo, "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQZ", 1);
+		let error = StagingConfig::from_json(&bad_address, "bad-address.json").unwrap_err();
+		assert!(error.starts_with("Error parsing staging config bad-address.json"), "{}", error);
+
+		let no_sudo = EXAMPLE_STAGING_CONFIG.replace(&format!("\"sudo\": \"{}\",", sudo), "");
+		let error = StagingConfig::from_json(&no_sudo, "no-sudo.json").unwrap_err();
+		assert!(error.contains("missing field `sudo`"), "{}", error);
+
+		let error = StagingConfig::from_json_file(&PathBuf::from("does-not-exist.json")).unwrap_err();
+		assert!(error.starts_with("Error opening staging config does-not-exist.json"), "{}", error);
+	}
+
+	#[test]
+	fn invalid_timing_is_rejected() {
+		assert_eq!(ConsensusTiming::default().validate(), Ok(()));
+		assert_eq!(ConsensusTiming::development().validate(), Ok(()));
+
+		let odd = EXAMPLE_STAGING_CONFIG.replace(
+			"\"bootNodes\": []",
+			"\"bootNodes\": [], \"timing\": { \"slotDuration\": 3001, \"epochDuration\": 100, \"primaryProbability\": [1, 4] }",
+		);
+		let error = StagingConfig::from_json(&odd, "odd.json").unwrap_err();
+		assert_eq!(error, "Invalid timing in staging config odd.json: Slot duration must be even and non-zero, got 3001");
+
+		let no_epoch = ConsensusTiming { epoch_duration: 0, ..Default::default() };
+		assert!(no_epoch.validate().is_err());
+		let certain = ConsensusTiming { primary_probability: (5, 4), ..Default::default() };
+		assert!(certain.validate().is_err());
+	}
+
+	#[test]
+	fn staging_needs_a_config_file() {
+		assert!(Alternative::from("staging").is_err());
+		assert!(Alternative::from("staging:does-not-exist.json").unwrap().unwrap().load().is_err());
+	}
+
+	#[test]
+	fn staging_spec_round_trips_through_build_spec() {
+		let dir = TempDir::new("kitties-spec").unwrap();
+		let config = dir.path().join("config.json");
+		fs::write(&config, EXAMPLE_STAGING_CONFIG).unwrap();
+		let spec = Alternative::from(&format!("staging:{}", config.display())).unwrap().unwrap().load().unwrap();
+		let json = spec.to_json(false).unwrap();
+
+		let path = dir.path().join("staging.json");
+		fs::write(&path, &json).unwrap();
+		let loaded = ChainSpec::from_json_file(path).unwrap();
+
+		assert_eq!((loaded.name(), loaded.id()), ("Kitties Staging", "kitties_staging"));
+		assert_eq!(loaded.properties(), spec.properties());
+		assert_eq!(loaded.to_json(false).unwrap(), json);
+		assert_eq!(loaded.to_json(true).unwrap(), spec.to_json(true).unwrap());
 	}
 }
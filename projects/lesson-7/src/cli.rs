@@ -3,10 +3,55 @@ use futures::{future, Future, sync::oneshot};
 use std::cell::RefCell;
 use tokio::runtime::Runtime;
 pub use substrate_cli::{VersionInfo, IntoExit, error};
-use substrate_cli::{informant, parse_and_prepare, ParseAndPrepare, NoCustom};
+use substrate_cli::{informant, parse_and_prepare, impl_augment_clap, ParseAndPrepare, GetLogFilter};
 use substrate_service::{AbstractService, Roles as ServiceRoles};
 use crate::chain_spec;
+use crate::inspect::{BalanceFormat, KittiesCmd};
+use crate::seal::SealMode;
 use log::info;
+use structopt::StructOpt;
+
+/// Extra flags of the default `run` command.
+#[derive(Debug, StructOpt, Clone)]
+pub struct RunParams {
+	/// Author a block as soon as a transaction enters the pool and finalize it right away.
+	/// BABE and GRANDPA do not run, so this is only for single node development chains.
+	#[structopt(long = "instant-seal", conflicts_with = "manual_seal")]
+	pub instant_seal: bool,
+
+	/// Author a block whenever the `engine_createBlock` RPC is called and finalize it right
+	/// away. BABE and GRANDPA do not run, so this is only for single node development chains.
+	#[structopt(long = "manual-seal")]
+	pub manual_seal: bool,
+}
+
+impl_augment_clap!(RunParams);
+
+/// Subcommands added to the standard ones.
+#[derive(Debug, StructOpt, Clone)]
+pub enum CustomSubcommands {
+	/// Inspect the kitties in the database of a stopped node.
+	#[structopt(name = "kitties")]
+	Kitties(KittiesCmd),
+}
+
+impl GetLogFilter for CustomSubcommands {
+	fn get_log_filter(&self) -> Option<String> {
+		match self {
+			CustomSubcommands::Kitties(cmd) => cmd.shared_params.log.clone(),
+		}
+	}
+}
+
+impl RunParams {
+	fn seal_mode(&self) -> Option<SealMode> {
+		match (self.instant_seal, self.manual_seal) {
+			(true, _) => Some(SealMode::Instant),
+			(_, true) => Some(SealMode::Manual),
+			_ => None,
+		}
+	}
+}
 
 /// Parse command line arguments into service configuration.
 pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()> where
@@ -14,15 +59,25 @@ pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()>
 	T: Into<std::ffi::OsString> + Clone,
 	E: IntoExit,
 {
-	match parse_and_prepare::<NoCustom, NoCustom, _>(&version, "substrate-node", args) {
+	match parse_and_prepare::<CustomSubcommands, RunParams, _>(&version, "substrate-node", args) {
 		ParseAndPrepare::Run(cmd) => cmd.run::<(), _, _, _, _>(load_spec, exit,
-		|exit, _cli_args, _custom_args, config| {
+		|exit, _cli_args, custom_args, config| {
 			info!("{}", version.name);
 			info!("  version {}", config.full_version());
 			info!("  by {}, 2017, 2018", version.author);
 			info!("Chain specification: {}", config.chain_spec.name());
+			let properties = config.chain_spec.properties();
+			info!(
+				"Token: {}, SS58 format {}",
+				BalanceFormat::from_properties(&properties),
+				properties.get("ss58Format").map(|v| v.to_string()).unwrap_or_else(|| "unset".into()),
+			);
 			info!("Node name: {}", config.name);
 			info!("Roles: {:?}", config.roles);
+			let seal = custom_args.seal_mode();
+			if let Some(mode) = seal {
+				info!("Sealing blocks in {:?} mode, BABE and GRANDPA are disabled", mode);
+			}
 			let runtime = Runtime::new().map_err(|e| format!("{:?}", e))?;
 			match config.roles {
 				ServiceRoles::LIGHT => run_until_exit(
@@ -32,7 +87,7 @@ pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()>
 				),
 				_ => run_until_exit(
 					runtime,
-					service::new_full(config).map_err(|e| format!("{:?}", e))?,
+					service::new_full(config, seal).map_err(|e| format!("{:?}", e))?,
 					exit
 				),
 			}.map_err(|e| format!("{:?}", e))
@@ -45,17 +100,19 @@ pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()>
 		ParseAndPrepare::PurgeChain(cmd) => cmd.run(load_spec),
 		ParseAndPrepare::RevertChain(cmd) => cmd.run_with_builder::<(), _, _, _, _>(|config|
 			Ok(new_full_start!(config).0), load_spec),
-		ParseAndPrepare::CustomCommand(_) => Ok(())
+		ParseAndPrepare::CustomCommand(CustomSubcommands::Kitties(cmd)) => cmd.run(load_spec, &version),
 	}?;
 
 	Ok(())
 }
 
+/// Anything that is not a known alternative is taken as the path of a JSON chain spec,
+/// like the ones written by `build-spec`.
 fn load_spec(id: &str) -> Result<Option<chain_spec::ChainSpec>, String> {
-	Ok(match chain_spec::Alternative::from(id) {
-		Some(spec) => Some(spec.load()?),
-		None => None,
-	})
+	Ok(Some(match chain_spec::Alternative::from(id)? {
+		Some(spec) => spec.load()?,
+		None => chain_spec::ChainSpec::from_json_file(std::path::PathBuf::from(id))?,
+	}))
 }
 
 fn run_until_exit<T, E>(
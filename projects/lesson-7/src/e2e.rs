@@ -0,0 +1,303 @@
+//! End-to-end tests that run a development node in process and drive it with signed kitties
+//! extrinsics, so calls go through the transaction pool, the executor and the real runtime
+//! rather than the mock in `kitties.rs`.
+//!
+//! The node uses `--instant-seal`, so submitted extrinsics are authored into a block and
+//! finalized as soon as the timestamp module allows another block.
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use codec::{Decode, Encode};
+use futures::Future;
+use primitives::{twox_128, sr25519, Pair, H256};
+use primitives::crypto::Ss58Codec;
+use primitives::storage::StorageKey;
+use sr_primitives::generic::BlockId;
+use sr_primitives::traits::ProvideRuntimeApi;
+use substrate_client::Client;
+use substrate_service::{AbstractService, Configuration, Roles};
+use tempdir::TempDir;
+use tokio::runtime::Runtime;
+use transaction_pool::txpool::Pool as TransactionPool;
+use kitties_client::{account_nonce_key, ChainInfo, Signer};
+use kitties_indexer::Indexer;
+use kitties_indexer::chain::HttpRpc;
+use kitties_indexer::db::Database;
+use substrate_kitties_runtime::{
+	AccountId, Balance, BlockNumber, Call, Event, Index, RuntimeApi, Runtime as KittiesRuntime,
+	UncheckedExtrinsic, VERSION, kitties,
+};
+use substrate_kitties_runtime::kitties::{KittiesApi, KittyDetails, LockReason};
+use substrate_kitties_runtime::opaque::Block;
+use crate::chain_spec::Alternative;
+use crate::seal::SealMode;
+use crate::service;
+
+/// How long to wait for a submitted extrinsic to be sealed.
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+type EventRecord = system::EventRecord<Event, H256>;
+
+/// A development node running in the background, with its database in a temporary directory.
+struct TestNode<S: AbstractService> {
+	client: Arc<Client<S::Backend, S::CallExecutor, Block, RuntimeApi>>,
+	pool: Arc<TransactionPool<S::TransactionPoolApi>>,
+	runtime: Option<Runtime>,
+	exit: Option<exit_future::Signal>,
+	_base_path: TempDir,
+}
+
+/// Start a development node with instant sealing.
+fn start_node() -> TestNode<impl AbstractService<Block = Block, RuntimeApi = RuntimeApi>> {
+	start_node_with_rpc(None)
+}
+
+/// Start a development node with instant sealing, serving HTTP RPC on `rpc_http` if given.
+fn start_node_with_rpc(rpc_http: Option<SocketAddr>) -> TestNode<impl AbstractService<Block = Block, RuntimeApi = RuntimeApi>> {
+	let base_path = TempDir::new("kitties-e2e").expect("Temporary directory can be created");
+	let spec = Alternative::Development.load().expect("The development chain spec is valid");
+	let mut config = Configuration::<(), _>::default_with_spec(spec);
+	config.roles = Roles::AUTHORITY;
+	config.database_path = base_path.path().join("db");
+	config.keystore_path = base_path.path().join("keystore");
+	config.network.net_config_path = Some(base_path.path().join("network").to_string_lossy().into());
+	config.network.listen_addresses = Vec::new();
+	config.rpc_http = rpc_http;
+	config.rpc_ws = None;
+	config.telemetry_endpoints = None;
+
+	let service = service::new_full(config, Some(SealMode::Instant)).expect("The node starts");
+	let client = service.client();
+	let pool = service.transaction_pool();
+
+	let (exit, on_exit) = exit_future::signal();
+	let mut runtime = Runtime::new().expect("Tokio runtime starts");
+	runtime.spawn(service.map_err(|e| panic!("Node failed: {:?}", e)).select(on_exit).then(|_| Ok(())));
+
+	TestNode {
+		client,
+		pool,
+		runtime: Some(runtime),
+		exit: Some(exit),
+		_base_path: base_path,
+	}
+}
+
+impl<S> TestNode<S> where
+	S: AbstractService<Block = Block, RuntimeApi = RuntimeApi>,
+{
+	/// Sign `call` with the next nonce of `signer`, submit it and wait for the block that
+	/// includes it. Returns the events of that block.
+	fn submit(&self, signer: &sr25519::Pair, call: kitties::Call<KittiesRuntime>) -> Vec<Event> {
+		self.submit_all(vec![(signer, call)])
+	}
+
+	/// Submit all `calls` without waiting in between, then wait until every one of them is in
+	/// a block. Returns the events of the blocks sealed meanwhile.
+	fn submit_all(&self, calls: Vec<(&sr25519::Pair, kitties::Call<KittiesRuntime>)>) -> Vec<Event> {
+		let best = self.best_number();
+		let mut next_nonces = HashMap::new();
+		for (signer, call) in calls {
+			let account = signer.account_id();
+			let nonce = next_nonces.get(&account).cloned().unwrap_or_else(|| self.account_nonce(&account));
+			next_nonces.insert(account, nonce + 1);
+
+			let xt = self.sign(signer, Call::Kitties(call), nonce);
+			let opaque = Decode::decode(&mut &xt.encode()[..]).expect("Extrinsics are encoded as opaque ones");
+			self.pool.submit_one(&BlockId::Number(best), opaque).expect("The pool accepts the extrinsic");
+		}
+
+		// Failed extrinsics use up their nonce too, their events are checked below
+		let started = Instant::now();
+		while next_nonces.iter().any(|(account, nonce)| self.account_nonce(account) < *nonce) {
+			assert!(started.elapsed() < BLOCK_TIMEOUT, "No block was sealed for the extrinsics");
+			thread::sleep(Duration::from_millis(20));
+		}
+
+		let events: Vec<_> = (best + 1..=self.best_number()).flat_map(|number| self.events(number)).collect();
+		for record in &events {
+			if let Event::system(system::Event::ExtrinsicFailed(..)) = record.event {
+				panic!("Extrinsic failed: {:?}", events);
+			}
+		}
+		events.into_iter().map(|record| record.event).collect()
+	}
+
+	fn sign(&self, signer: &sr25519::Pair, call: Call, nonce: Index) -> UncheckedExtrinsic {
+		let chain = ChainInfo {
+			genesis_hash: self.client.info().chain.genesis_hash,
+			spec_version: VERSION.spec_version,
+		};
+		kitties_client::sign(signer, call, nonce, 0, &chain)
+	}
+
+	/// The nonce of an account at the best block.
+	fn account_nonce(&self, account: &AccountId) -> Index {
+		self.storage(&account_nonce_key(account)).unwrap_or_default()
+	}
+
+	/// The events of block `number`.
+	fn events(&self, number: BlockNumber) -> Vec<EventRecord> {
+		self.storage_at(&BlockId::Number(number), &twox_128(b"System Events")).unwrap_or_default()
+	}
+
+	fn storage<T: Decode>(&self, key: &[u8]) -> Option<T> {
+		self.storage_at(&self.best(), key)
+	}
+
+	fn storage_at<T: Decode>(&self, at: &BlockId<Block>, key: &[u8]) -> Option<T> {
+		self.client.storage(at, &StorageKey(key.to_vec()))
+			.expect("Best block state is available")
+			.map(|data| Decode::decode(&mut &data.0[..]).expect("Storage decodes as the runtime wrote it"))
+	}
+
+	fn best(&self) -> BlockId<Block> {
+		BlockId::Hash(self.client.info().chain.best_hash)
+	}
+
+	fn kitty(&self, kitty_id: u32) -> Option<KittyDetails<AccountId, Balance>> {
+		self.client.runtime_api().kitty(&self.best(), kitty_id).expect("Runtime API call succeeds")
+	}
+
+	fn owned(&self, owner: &AccountId) -> Vec<u32> {
+		self.client.runtime_api().owned(&self.best(), owner.clone(), None, 100).expect("Runtime API call succeeds")
+	}
+
+	fn check_integrity(&self) {
+		let result = self.client.runtime_api().check_integrity(&self.best()).expect("Runtime API call succeeds");
+		assert_eq!(result.map_err(|e| String::from_utf8_lossy(&e).into_owned()), Ok(()));
+	}
+
+	fn best_number(&self) -> BlockNumber {
+		self.client.info().chain.best_number
+	}
+}
+
+impl<S: AbstractService> Drop for TestNode<S> {
+	fn drop(&mut self) {
+		if let Some(exit) = self.exit.take() {
+			exit.fire();
+		}
+		if let Some(runtime) = self.runtime.take() {
+			let _ = runtime.shutdown_now().wait();
+		}
+	}
+}
+
+/// A local address nothing listens on right now.
+fn free_local_address() -> SocketAddr {
+	let listener = TcpListener::bind("127.0.0.1:0").expect("A local port is free");
+	listener.local_addr().expect("Bound listeners have an address")
+}
+
+fn alice() -> sr25519::Pair {
+	sr25519::Pair::from_string("//Alice", None).expect("Dev seeds are valid")
+}
+
+fn bob() -> sr25519::Pair {
+	sr25519::Pair::from_string("//Bob", None).expect("Dev seeds are valid")
+}
+
+fn kitties_event(event: kitties::RawEvent<AccountId, H256, BlockNumber, u32, Balance>) -> Event {
+	Event::kitties(event)
+}
+
+#[test]
+fn kitties_are_created_transferred_and_sold() {
+	let node = start_node();
+	let (alice, bob) = (alice(), bob());
+
+	let events = node.submit(&alice, kitties::Call::create());
+	assert!(events.contains(&kitties_event(kitties::RawEvent::Created(alice.public(), 0))));
+	assert_eq!(node.owned(&alice.public()), vec![0]);
+
+	let events = node.submit(&alice, kitties::Call::transfer(bob.public(), 0));
+	assert!(events.contains(&kitties_event(kitties::RawEvent::Transferred(alice.public(), bob.public(), 0))));
+	assert_eq!(node.kitty(0).map(|kitty| kitty.owner), Some(bob.public()));
+
+	let events = node.submit(&bob, kitties::Call::ask(0, Some(1_000)));
+	assert!(events.contains(&kitties_event(kitties::RawEvent::Ask(bob.public(), 0, Some(1_000)))));
+	let listed = node.kitty(0).expect("Kitty 0 exists");
+	assert_eq!((listed.price, listed.lock), (Some(1_000), Some(LockReason::Listed)));
+
+	// The single ask so far moved the listing nonce from 0 to 1
+	let events = node.submit(&alice, kitties::Call::buy(0, 1, 1_000));
+	assert!(events.contains(&kitties_event(kitties::RawEvent::Sold(bob.public(), alice.public(), 0, 1_000))));
+	let sold = node.kitty(0).expect("Kitty 0 exists");
+	assert_eq!((sold.owner, sold.price, sold.lock), (alice.public(), None, None));
+	assert_eq!(node.owned(&bob.public()), Vec::<u32>::new());
+
+	assert!(node.best_number() >= 4);
+	node.check_integrity();
+}
+
+#[test]
+fn kitties_are_bred() {
+	let node = start_node();
+	let alice = alice();
+
+	node.submit(&alice, kitties::Call::create());
+	node.submit(&alice, kitties::Call::create());
+	let events = node.submit(&alice, kitties::Call::breed(0, 1));
+	assert!(events.contains(&kitties_event(kitties::RawEvent::Created(alice.public(), 2))));
+
+	assert_eq!(node.owned(&alice.public()), vec![0, 1, 2]);
+	assert!(node.kitty(2).is_some());
+	node.check_integrity();
+}
+
+#[test]
+fn back_to_back_extrinsics_are_sealed() {
+	let node = start_node();
+	let (alice, bob) = (alice(), bob());
+
+	// Both arrive right after the block before, well within the minimum period of the
+	// timestamp module, so the seal has to wait for it rather than fail
+	node.submit(&alice, kitties::Call::create());
+	let events = node.submit_all(vec![
+		(&alice, kitties::Call::create()),
+		(&bob, kitties::Call::create()),
+	]);
+
+	let created = events.iter()
+		.filter(|event| match event {
+			Event::kitties(kitties::RawEvent::Created(..)) => true,
+			_ => false,
+		})
+		.count();
+	assert_eq!(created, 2);
+	assert_eq!(node.owned(&alice.public()).len(), 2);
+	assert_eq!(node.owned(&bob.public()).len(), 1);
+	node.check_integrity();
+}
+
+#[test]
+fn indexer_follows_the_node() {
+	let address = free_local_address();
+	let node = start_node_with_rpc(Some(address));
+	let rpc = HttpRpc::new(&format!("http://{}", address));
+	let addresses = rpc.address_format().expect("The node has chain properties");
+	let mut indexer = Indexer::new(rpc, Database::in_memory(addresses).unwrap());
+	let (alice, bob) = (alice(), bob());
+
+	node.submit(&alice, kitties::Call::create());
+	node.submit(&alice, kitties::Call::ask(0, Some(500)));
+	// The ask was the first listing of the kitty, so its listing nonce is 1
+	node.submit(&bob, kitties::Call::buy(0, 1, 500));
+	indexer.sync().expect("The node can be indexed");
+
+	let db = indexer.database();
+	let (alice_address, bob_address) = (
+		alice.public().to_ss58check_with_version(addresses),
+		bob.public().to_ss58check_with_version(addresses),
+	);
+	assert_eq!(db.last_block(), Ok(Some(node.best_number())));
+	assert_eq!(db.owner(0), Ok(Some(bob_address.clone())));
+	assert_eq!(db.listing_price(0), Ok(None));
+	let trades = db.trades(0).unwrap();
+	assert_eq!(trades.len(), 1);
+	assert_eq!((&trades[0].seller, &trades[0].buyer, &trades[0].price[..]), (&alice_address, &bob_address, "500"));
+}
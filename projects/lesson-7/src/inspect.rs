@@ -0,0 +1,337 @@
+//! The `kitties` subcommand, which answers questions about kitties from the node database
+//! without starting networking or RPC.
+//!
+//! The database can only be opened by one process, so this is run against a stopped node.
+//! The client has no read-only mode, so the database is opened for writing like the node
+//! opens it, but only the client is built, without the consensus import pipeline, so no block
+//! is imported. A missing database is an error rather than a new chain. Storage is read
+//! through the kitties runtime API, decoded by the native runtime when it matches the one on
+//! chain, at a single block, so paging through a list sees one consistent state. Addresses are
+//! read and printed in the SS58 format of the chain spec.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use primitives::H256;
+use primitives::crypto::{Ss58AddressFormat, Ss58Codec};
+use primitives::hexdisplay::HexDisplay;
+use sr_primitives::generic::BlockId;
+use sr_primitives::traits::{Header as HeaderT, ProvideRuntimeApi};
+use structopt::StructOpt;
+use substrate_cli::{SharedParams, VersionInfo, create_config_with_db_path, error};
+use substrate_client::ExecutionStrategy;
+use substrate_service::{Properties, ServiceBuilder};
+use substrate_kitties_runtime::{AccountId, Balance, BlockNumber, RuntimeApi, DOLLARS, TOKEN_SYMBOL, TOKEN_DECIMALS, SS58_FORMAT};
+use substrate_kitties_runtime::kitties::{KittiesApi, KittyDetails};
+use substrate_kitties_runtime::opaque::Block;
+use crate::chain_spec::ChainSpec;
+use crate::service::Executor;
+use crate::snapshot::{KittiesSnapshot, SnapshotKitty};
+
+/// The most kitties requested from the runtime in one call.
+const PAGE_SIZE: u32 = 100;
+
+/// The `kitties` subcommand.
+#[derive(Debug, StructOpt, Clone)]
+pub struct KittiesCmd {
+	/// Block hash or number to read the kitties at, the best block if not given.
+	/// Pruned nodes only keep the state of recent blocks.
+	#[structopt(long = "at", value_name = "BLOCK")]
+	pub at: Option<BlockRef>,
+
+	#[structopt(subcommand)]
+	pub query: KittiesQuery,
+
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+}
+
+/// What to look up.
+#[derive(Debug, StructOpt, Clone)]
+pub enum KittiesQuery {
+	/// List every kitty
+	#[structopt(name = "list")]
+	List,
+	/// Show one kitty
+	#[structopt(name = "show")]
+	Show {
+		/// Index of the kitty
+		kitty_id: u32,
+	},
+	/// List the kitties of an account
+	#[structopt(name = "owned")]
+	Owned {
+		/// SS58 address of the owner, in the format of the chain
+		account: String,
+	},
+	/// List the kitties for sale
+	#[structopt(name = "listings")]
+	Listings {
+		/// Only list the kitties of this seller, an SS58 address in the format of the chain
+		#[structopt(long = "seller")]
+		seller: Option<String>,
+	},
+	/// Write every kitty to a JSON snapshot, which a staging config can use as the
+	/// kitties of a new chain
+	#[structopt(name = "export")]
+	Export {
+		/// File to write the snapshot to
+		#[structopt(parse(from_os_str))]
+		path: PathBuf,
+	},
+}
+
+/// A block given by hash or number.
+#[derive(Debug, Clone, Copy)]
+pub enum BlockRef {
+	/// A `0x` prefixed block hash
+	Hash(H256),
+	/// A block number on the best chain
+	Number(BlockNumber),
+}
+
+impl FromStr for BlockRef {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if s.starts_with("0x") {
+			H256::from_str(&s[2..])
+				.map(BlockRef::Hash)
+				.map_err(|e| format!("Invalid block hash {}: {:?}", s, e))
+		} else {
+			s.parse()
+				.map(BlockRef::Number)
+				.map_err(|e| format!("Invalid block number {}: {}", s, e))
+		}
+	}
+}
+
+/// Reads and writes addresses in the SS58 format of the chain spec.
+pub struct AddressFormat(Ss58AddressFormat);
+
+impl AddressFormat {
+	/// Read the format from chain spec properties, falling back to the runtime's format.
+	pub fn from_properties(properties: &Properties) -> Self {
+		let version = properties.get("ss58Format")
+			.and_then(|v| v.as_u64())
+			.map(|v| v as u8)
+			.unwrap_or(SS58_FORMAT);
+		AddressFormat(Ss58AddressFormat::try_from(version).unwrap_or(Ss58AddressFormat::Custom(version)))
+	}
+
+	/// Parse an address, which has to be in this format.
+	pub fn parse(&self, s: &str) -> Result<AccountId, String> {
+		let (account, version) = AccountId::from_ss58check_with_version(s)
+			.map_err(|e| format!("Invalid address {}: {:?}", s, e))?;
+		if version != self.0 {
+			return Err(format!("Address {} is in SS58 format {}, the chain uses {}", s, u8::from(version), u8::from(self.0)));
+		}
+		Ok(account)
+	}
+
+	/// The address of `account`.
+	pub fn format(&self, account: &AccountId) -> String {
+		account.to_ss58check_with_version(self.0)
+	}
+}
+
+/// Formats balances with the token symbol and decimals of the chain spec.
+pub struct BalanceFormat {
+	symbol: String,
+	decimals: u32,
+	/// One whole token in the smallest unit
+	unit: Balance,
+}
+
+impl BalanceFormat {
+	/// Read the token from chain spec properties, falling back to the runtime's token.
+	pub fn from_properties(properties: &Properties) -> Self {
+		let decimals = properties.get("tokenDecimals")
+			.and_then(|v| v.as_u64())
+			.map(|v| v as u32);
+		BalanceFormat {
+			symbol: properties.get("tokenSymbol")
+				.and_then(|v| v.as_str())
+				.unwrap_or(TOKEN_SYMBOL)
+				.into(),
+			decimals: decimals.unwrap_or(TOKEN_DECIMALS),
+			unit: decimals.map(|d| 10u128.saturating_pow(d)).unwrap_or(DOLLARS),
+		}
+	}
+
+	/// The balance in whole tokens, without trailing zeros.
+	pub fn format(&self, amount: Balance) -> String {
+		let unit = self.unit;
+		let fraction = format!("{:0width$}", amount % unit, width = self.decimals as usize);
+		let fraction = fraction.trim_end_matches('0');
+		if fraction.is_empty() {
+			format!("{} {}", amount / unit, self.symbol)
+		} else {
+			format!("{}.{} {}", amount / unit, fraction, self.symbol)
+		}
+	}
+}
+
+impl fmt::Display for BalanceFormat {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} with {} decimals, 1 {} = {} units", self.symbol, self.decimals, self.symbol, self.unit)
+	}
+}
+
+impl KittiesCmd {
+	/// Open the database of the chain given by `--chain` and print the answer to the query.
+	pub fn run<S>(self, spec_factory: S, version: &VersionInfo) -> error::Result<()> where
+		S: FnOnce(&str) -> Result<Option<ChainSpec>, String>,
+	{
+		let mut config = create_config_with_db_path::<(), _, _>(spec_factory, &self.shared_params, version)?;
+		config.execution_strategies.other = ExecutionStrategy::NativeElseWasm;
+		let balances = BalanceFormat::from_properties(&config.chain_spec.properties());
+		let addresses = AddressFormat::from_properties(&config.chain_spec.properties());
+
+		// Opening a missing database would create it with a new genesis block
+		if !config.database_path.join("CURRENT").exists() {
+			return Err(format!(
+				"No database for chain {} at {}, run the node first", config.chain_spec.id(), config.database_path.display(),
+			).into());
+		}
+
+		let builder = ServiceBuilder::new_full::<Block, RuntimeApi, Executor>(config)?;
+		let client = builder.client();
+		let at = match self.at {
+			Some(BlockRef::Hash(hash)) => BlockId::Hash(hash),
+			Some(BlockRef::Number(number)) => BlockId::Number(number),
+			None => BlockId::Hash(client.info().chain.best_hash),
+		};
+		// Every page is read from the same state
+		let at = client.header(&at).map_err(|e| format!("Reading block {} failed: {:?}", at, e))?
+			.map(|header| BlockId::Hash(header.hash()))
+			.ok_or_else(|| format!("Block {} not found", at))?;
+		let api = client.runtime_api();
+		let runtime_error = |e| format!("Reading kitties at {} failed: {:?}", at, e);
+		let details = |kitty_id| api.kitty(&at, kitty_id).map_err(runtime_error);
+
+		match self.query {
+			KittiesQuery::List => {
+				let count = api.kitties_count(&at).map_err(runtime_error)?;
+				println!("{} kitties at {}", count, at);
+				for kitty_id in 0..count {
+					if let Some(kitty) = details(kitty_id)? {
+						println!("{}", summary(kitty_id, &kitty, &balances, &addresses));
+					}
+				}
+			}
+			KittiesQuery::Show { kitty_id } => {
+				let kitty = details(kitty_id)?
+					.ok_or_else(|| format!("Kitty {} does not exist at {}", kitty_id, at))?;
+				println!("Kitty {}", kitty_id);
+				println!("  DNA:   0x{}", HexDisplay::from(&kitty.dna));
+				println!("  Owner: {}", addresses.format(&kitty.owner));
+				println!("  Price: {}", kitty.price.map(|price| balances.format(price)).unwrap_or_else(|| "not for sale".into()));
+				println!("  Lock:  {}", kitty.lock.map(|lock| format!("{:?}", lock)).unwrap_or_else(|| "none".into()));
+			}
+			KittiesQuery::Owned { account } => {
+				let account = addresses.parse(&account)?;
+				let mut after = None;
+				loop {
+					let page = api.owned(&at, account.clone(), after, PAGE_SIZE).map_err(runtime_error)?;
+					for &kitty_id in &page {
+						if let Some(kitty) = details(kitty_id)? {
+							println!("{}", summary(kitty_id, &kitty, &balances, &addresses));
+						}
+					}
+					if page.len() < PAGE_SIZE as usize {
+						break;
+					}
+					after = page.last().cloned();
+				}
+			}
+			KittiesQuery::Listings { seller } => {
+				let seller = seller.map(|seller| addresses.parse(&seller)).transpose()?;
+				let mut after = None;
+				loop {
+					let page = api.listings(&at, seller.clone(), after, PAGE_SIZE).map_err(runtime_error)?;
+					for &(kitty_id, price) in &page {
+						let owner = details(kitty_id)?
+							.map(|kitty| addresses.format(&kitty.owner))
+							.unwrap_or_default();
+						println!("#{} {} {}", kitty_id, balances.format(price), owner);
+					}
+					if page.len() < PAGE_SIZE as usize {
+						break;
+					}
+					after = page.last().map(|&(kitty_id, _)| kitty_id);
+				}
+			}
+			KittiesQuery::Export { path } => {
+				let header = client.header(&at).map_err(runtime_error)?
+					.ok_or_else(|| format!("Block {} not found", at))?;
+				let count = api.kitties_count(&at).map_err(runtime_error)?;
+				let mut kitties = Vec::new();
+				for kitty_id in 0..count {
+					if let Some(kitty) = details(kitty_id)? {
+						kitties.push(SnapshotKitty::new(kitty_id, kitty));
+					}
+				}
+				let snapshot = KittiesSnapshot {
+					block_hash: header.hash(),
+					block_number: *header.number(),
+					kitties,
+				};
+				snapshot.write_json_file(&path)?;
+				println!(
+					"Exported {} kitties at block #{} ({}) to {}",
+					snapshot.kitties.len(), snapshot.block_number, snapshot.block_hash, path.display(),
+				);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// One line describing a kitty.
+fn summary(kitty_id: u32, kitty: &KittyDetails<AccountId, Balance>, balances: &BalanceFormat, addresses: &AddressFormat) -> String {
+	let mut line = format!("#{} 0x{} {}", kitty_id, HexDisplay::from(&kitty.dna), addresses.format(&kitty.owner));
+	if let Some(price) = kitty.price {
+		line.push_str(&format!(" for sale at {}", balances.format(price)));
+	}
+	if let Some(lock) = kitty.lock {
+		line.push_str(&format!(" locked ({:?})", lock));
+	}
+	line
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn balances_are_formatted_in_whole_tokens() {
+		let balances = BalanceFormat::from_properties(&Properties::new());
+		assert_eq!(balances.format(3 * DOLLARS), "3 KTY");
+		assert_eq!(balances.format(3 * DOLLARS / 2), "1.5 KTY");
+		assert_eq!(balances.format(1), "0.000000000001 KTY");
+
+		let mut properties = Properties::new();
+		properties.insert("tokenSymbol".into(), "TST".into());
+		properties.insert("tokenDecimals".into(), 3.into());
+		assert_eq!(BalanceFormat::from_properties(&properties).format(1_250), "1.25 TST");
+	}
+
+	#[test]
+	fn addresses_use_the_format_of_the_chain() {
+		let alice = AccountId::from_ss58check("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY").unwrap();
+		let substrate = AddressFormat::from_properties(&Properties::new());
+		assert_eq!(substrate.format(&alice), "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY");
+		assert_eq!(substrate.parse("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"), Ok(alice.clone()));
+
+		let mut properties = Properties::new();
+		properties.insert("ss58Format".into(), 2.into());
+		let kusama = AddressFormat::from_properties(&properties);
+		let address = kusama.format(&alice);
+		assert!(address != substrate.format(&alice));
+		assert_eq!(kusama.parse(&address), Ok(alice));
+		assert!(kusama.parse("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY").is_err());
+	}
+}
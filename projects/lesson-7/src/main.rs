@@ -6,7 +6,12 @@
 mod chain_spec;
 #[macro_use]
 mod service;
+mod inspect;
+mod seal;
+mod snapshot;
 mod cli;
+#[cfg(test)]
+mod e2e;
 
 pub use substrate_cli::{VersionInfo, IntoExit, error};
 
@@ -0,0 +1,230 @@
+//! Development block authoring without BABE slots or GRANDPA votes.
+//!
+//! `--instant-seal` authors a block as soon as a transaction enters the pool, `--manual-seal`
+//! authors one whenever the `engine_createBlock` RPC is called. Either way the block is
+//! finalized on import, so a single node makes progress without waiting for slots. Blocks are
+//! still kept the minimum period of the timestamp module apart, half a slot.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use consensus_common::{
+	BlockImport, BlockImportParams, BlockOrigin, Environment, ForkChoiceStrategy, ImportResult,
+	Proposer, Error as ConsensusError,
+};
+use futures::{future, Future, IntoFuture, Stream};
+use futures::sync::oneshot;
+use inherents::InherentDataProviders;
+use jsonrpc_core::{BoxFuture, Error as RpcError, ErrorCode};
+use jsonrpc_derive::rpc;
+use log::{error, warn};
+use parking_lot::Mutex;
+use sr_primitives::generic::BlockId;
+use sr_primitives::traits::{Block as BlockT, Header as HeaderT};
+use substrate_client::blockchain::HeaderBackend;
+use substrate_kitties_runtime::opaque::Block;
+
+/// How long the proposer may spend filling a block with transactions.
+const PROPOSAL_DURATION: Duration = Duration::from_secs(2);
+
+/// How often a failed instant seal is retried before waiting for the next transaction.
+const INSTANT_SEAL_RETRIES: u32 = 3;
+
+type Hash = <Block as BlockT>::Hash;
+
+/// How blocks are authored by a development node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SealMode {
+	/// Author a block whenever a transaction enters the pool
+	Instant,
+	/// Author a block whenever `engine_createBlock` is called
+	Manual,
+}
+
+/// Authors blocks on top of the best block and imports them as final.
+pub struct Sealer<C, E> {
+	client: Arc<C>,
+	env: E,
+	inherent_data_providers: InherentDataProviders,
+	/// The minimum period of the timestamp module
+	min_interval: Duration,
+	last_attempt: Option<Instant>,
+}
+
+impl<C, E> Sealer<C, E> where
+	C: HeaderBackend<Block>,
+	for<'a> &'a C: BlockImport<Block, Error = ConsensusError>,
+	E: Environment<Block>,
+	E::Error: Debug,
+	<E::Proposer as Proposer<Block>>::Error: Debug,
+{
+	pub fn new(client: Arc<C>, env: E, inherent_data_providers: InherentDataProviders, min_interval: Duration) -> Self {
+		Sealer { client, env, inherent_data_providers, min_interval, last_attempt: None }
+	}
+
+	/// Sleep until `min_interval` has passed since the last attempt to seal, so that the
+	/// timestamp module accepts the next block.
+	pub fn wait_for_slot(&self) {
+		if let Some(wait) = self.last_attempt.and_then(|at| self.min_interval.checked_sub(at.elapsed())) {
+			thread::sleep(wait);
+		}
+	}
+
+	/// Author a block with the transactions in the pool and import it as final.
+	pub fn seal(&mut self) -> Result<Hash, String> {
+		let result = self.try_seal();
+		self.last_attempt = Some(Instant::now());
+		result
+	}
+
+	fn try_seal(&mut self) -> Result<Hash, String> {
+		let best_hash = self.client.info().best_hash;
+		let parent = self.client.header(BlockId::Hash(best_hash))
+			.map_err(|e| format!("{:?}", e))?
+			.ok_or_else(|| format!("Best block {} has no header", best_hash))?;
+
+		let mut proposer = self.env.init(&parent).map_err(|e| format!("{:?}", e))?;
+		let inherent_data = self.inherent_data_providers.create_inherent_data()
+			.map_err(|e| format!("{:?}", e))?;
+		let block = proposer.propose(inherent_data, Default::default(), PROPOSAL_DURATION)
+			.into_future()
+			.wait()
+			.map_err(|e| format!("{:?}", e))?;
+
+		let (header, body) = block.deconstruct();
+		let hash = header.hash();
+		let params = BlockImportParams {
+			origin: BlockOrigin::Own,
+			header,
+			justification: None,
+			post_digests: Vec::new(),
+			body: Some(body),
+			finalized: true,
+			auxiliary: Vec::new(),
+			fork_choice: ForkChoiceStrategy::LongestChain,
+		};
+
+		match (&*self.client).import_block(params, HashMap::new()).map_err(|e| format!("{:?}", e))? {
+			ImportResult::Imported(_) => Ok(hash),
+			other => Err(format!("Sealed block {} was not imported: {:?}", hash, other)),
+		}
+	}
+}
+
+/// Seal on a thread of its own whenever `imports` yields and `ready` counts transactions, so
+/// that authoring never blocks the executor. A failed seal is retried after the sealer's
+/// minimum interval.
+///
+/// The returned future forwards `imports` to the thread and has to be spawned. The thread
+/// stops once the future is dropped.
+pub fn instant_seal<C, E, I>(
+	mut sealer: Sealer<C, E>,
+	ready: impl Fn() -> usize + Send + 'static,
+	imports: I,
+) -> impl Future<Item = (), Error = ()> where
+	C: HeaderBackend<Block> + Send + Sync + 'static,
+	for<'a> &'a C: BlockImport<Block, Error = ConsensusError>,
+	E: Environment<Block> + Send + 'static,
+	E::Error: Debug,
+	<E::Proposer as Proposer<Block>>::Error: Debug,
+	I: Stream<Error = ()>,
+{
+	let (wake, woken) = mpsc::channel();
+	thread::Builder::new()
+		.name("instant-seal".into())
+		.spawn(move || {
+			while woken.recv().is_ok() {
+				// One block takes every transaction that arrived so far
+				while woken.try_recv().is_ok() {}
+
+				let mut failures = 0;
+				loop {
+					// Waiting first also gives the pool time to prune what the last block included
+					sealer.wait_for_slot();
+					if ready() == 0 {
+						break;
+					}
+
+					if let Err(e) = sealer.seal() {
+						failures += 1;
+						if failures > INSTANT_SEAL_RETRIES {
+							error!("Instant seal failed, waiting for the next transaction: {}", e);
+							break;
+						}
+						warn!("Instant seal failed, retrying in {:?}: {}", sealer.min_interval, e);
+					}
+				}
+			}
+		})
+		.expect("The instant seal thread can be spawned");
+
+	imports.for_each(move |_| {
+		// The thread only stops after this future is dropped, so it is still listening
+		let _ = wake.send(());
+		Ok(())
+	})
+}
+
+/// RPC methods of `--manual-seal`.
+#[rpc]
+pub trait SealApi<Hash> {
+	/// Author a block with the transactions in the pool and finalize it. Waits until the
+	/// minimum period of the timestamp module has passed since the last block.
+	#[rpc(name = "engine_createBlock")]
+	fn create_block(&self) -> BoxFuture<Hash>;
+}
+
+type SealReply = oneshot::Sender<Result<Hash, String>>;
+
+/// Implements `SealApi` by handing requests to a thread of its own, so that authoring never
+/// blocks an RPC worker. Requests are sealed one at a time, in the order they arrive.
+pub struct ManualSeal {
+	requests: Mutex<mpsc::Sender<SealReply>>,
+}
+
+impl ManualSeal {
+	/// Start the sealing thread, which stops once the `ManualSeal` is dropped.
+	pub fn new<C, E>(mut sealer: Sealer<C, E>) -> Self where
+		C: HeaderBackend<Block> + Send + Sync + 'static,
+		for<'a> &'a C: BlockImport<Block, Error = ConsensusError>,
+		E: Environment<Block> + Send + 'static,
+		E::Error: Debug,
+		<E::Proposer as Proposer<Block>>::Error: Debug,
+	{
+		let (requests, received) = mpsc::channel::<SealReply>();
+		thread::Builder::new()
+			.name("manual-seal".into())
+			.spawn(move || {
+				for reply in received {
+					sealer.wait_for_slot();
+					// The caller may have gone away in the meantime
+					let _ = reply.send(sealer.seal());
+				}
+			})
+			.expect("The manual seal thread can be spawned");
+		ManualSeal { requests: Mutex::new(requests) }
+	}
+}
+
+fn seal_error(message: String) -> RpcError {
+	RpcError {
+		code: ErrorCode::InternalError,
+		message,
+		data: None,
+	}
+}
+
+impl SealApi<Hash> for ManualSeal {
+	fn create_block(&self) -> BoxFuture<Hash> {
+		let (reply, sealed) = oneshot::channel();
+		if self.requests.lock().send(reply).is_err() {
+			return Box::new(future::err(seal_error("The manual seal thread stopped".into())));
+		}
+		Box::new(sealed.then(|result| match result {
+			Ok(sealed) => sealed.map_err(seal_error),
+			Err(_) => Err(seal_error("The manual seal thread stopped".into())),
+		}))
+	}
+}
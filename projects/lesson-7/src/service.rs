@@ -13,6 +13,11 @@ use inherents::InherentDataProviders;
 use network::construct_simple_protocol;
 use substrate_executor::native_executor_instance;
 pub use substrate_executor::NativeExecutor;
+use sr_primitives::generic::BlockId;
+use sr_primitives::traits::ProvideRuntimeApi;
+use babe_primitives::BabeApi;
+use crate::chain_spec::FinalitySettings;
+use crate::seal::{self, SealMode, Sealer, ManualSeal, SealApi};
 
 // Our native executor instance.
 native_executor_instance!(
@@ -76,21 +81,51 @@ macro_rules! new_full_start {
 }
 
 /// Builds a new service for a full client.
-pub fn new_full<C: Send + Default + 'static>(config: Configuration<C, GenesisConfig>)
-	-> Result<impl AbstractService, ServiceError>
+///
+/// With a `seal` mode the node authors and finalizes blocks on its own, and neither BABE nor
+/// GRANDPA runs. This is only meant for single node development chains.
+pub fn new_full<C: Send + Default + 'static>(config: Configuration<C, GenesisConfig>, seal: Option<SealMode>)
+	-> Result<impl AbstractService<Block = Block, RuntimeApi = RuntimeApi>, ServiceError>
 {
 
 	let is_authority = config.roles.is_authority();
 	let name = config.name.clone();
 	let disable_grandpa = config.disable_grandpa;
 	let force_authoring = config.force_authoring;
+	let finality = FinalitySettings::from_properties(&config.chain_spec.properties());
 
 	let (builder, mut import_setup, inherent_data_providers, mut tasks_to_spawn) = new_full_start!(config);
 
+	// The timestamp module rejects blocks less than half a slot after their parent, so sealed
+	// blocks are kept that far apart
+	let min_seal_interval = if seal.is_some() {
+		let client = builder.client();
+		let best = BlockId::Hash(client.info().chain.best_hash);
+		let slot_duration = client.runtime_api().configuration(&best)
+			.map_err(|e| ServiceError::Other(format!("Error reading the slot duration: {:?}", e)))?
+			.slot_duration;
+		Duration::from_millis(slot_duration / 2)
+	} else {
+		Duration::default()
+	};
+
+	let rpc_inherent_data_providers = inherent_data_providers.clone();
 	let service = builder.with_network_protocol(|_| Ok(NodeProtocol::new()))?
 		.with_finality_proof_provider(|client, backend|
 			Ok(Arc::new(GrandpaFinalityProofProvider::new(backend, client)) as _)
 		)?
+		.with_rpc_extensions(|client, pool| {
+			let mut io = jsonrpc_core::IoHandler::<substrate_service::RpcMetadata>::default();
+			if seal == Some(SealMode::Manual) {
+				let proposer = basic_authorship::ProposerFactory {
+					client: client.clone(),
+					transaction_pool: pool,
+				};
+				let sealer = Sealer::new(client, proposer, rpc_inherent_data_providers, min_seal_interval);
+				io.extend_with(SealApi::to_delegate(ManualSeal::new(sealer)));
+			}
+			io
+		})?
 		.build()?;
 
 	let (block_import, link_half, babe_link) =
@@ -108,6 +143,29 @@ pub fn new_full<C: Send + Default + 'static>(config: Configuration<C, GenesisCon
 		}
 	}
 
+	if let Some(mode) = seal {
+		grandpa::setup_disabled_grandpa(
+			service.client(),
+			&inherent_data_providers,
+			service.network(),
+		)?;
+
+		if mode == SealMode::Instant {
+			let proposer = basic_authorship::ProposerFactory {
+				client: service.client(),
+				transaction_pool: service.transaction_pool(),
+			};
+			let sealer = Sealer::new(service.client(), proposer, inherent_data_providers, min_seal_interval);
+			let pool = service.transaction_pool();
+
+			let imports = pool.import_notification_stream();
+			let instant_seal = seal::instant_seal(sealer, move || pool.status().ready, imports);
+			service.spawn_essential_task(instant_seal.select(service.on_exit()).then(|_| Ok(())));
+		}
+
+		return Ok(service);
+	}
+
 	if is_authority {
 		let proposer = basic_authorship::ProposerFactory {
 			client: service.client(),
@@ -140,9 +198,8 @@ pub fn new_full<C: Send + Default + 'static>(config: Configuration<C, GenesisCon
 	}
 
 	let grandpa_config = grandpa::Config {
-		// FIXME #1578 make this available through chainspec
-		gossip_duration: Duration::from_millis(333),
-		justification_period: 4096,
+		gossip_duration: Duration::from_millis(finality.gossip_duration),
+		justification_period: finality.justification_period,
 		name: Some(name),
 		keystore: Some(service.keystore()),
 	};
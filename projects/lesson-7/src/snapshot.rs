@@ -0,0 +1,142 @@
+//! JSON snapshots of the kitties of a chain, written by `kitties export` and read back into the
+//! genesis of a new chain by the `kittiesSnapshot` field of a staging config.
+//!
+//! Only what genesis can recreate is kept: DNA, owner and asking price. Rentals, loans,
+//! transfer offers and sale history are left behind, and the runtime does not record lineage.
+
+use std::fs::File;
+use std::path::Path;
+use primitives::{Bytes, H256};
+use serde::{Deserialize, Serialize};
+use substrate_kitties_runtime::{AccountId, Balance, BlockNumber};
+use substrate_kitties_runtime::kitties::KittyDetails;
+
+/// The kitties of a chain at a block.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct KittiesSnapshot {
+	/// Hash of the block the snapshot was taken at
+	pub block_hash: H256,
+	/// Number of the block the snapshot was taken at
+	pub block_number: BlockNumber,
+	/// Every kitty, in index order
+	pub kitties: Vec<SnapshotKitty>,
+}
+
+/// A kitty in a `KittiesSnapshot`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SnapshotKitty {
+	/// Index of the kitty on the original chain
+	pub id: u32,
+	/// The kitty's 16 byte DNA, `0x` prefixed hex
+	pub dna: Bytes,
+	/// SS58 address of the owner
+	pub owner: AccountId,
+	/// The asking price, null if not for sale
+	pub price: Option<Balance>,
+}
+
+impl SnapshotKitty {
+	/// The parts of a kitty a snapshot keeps.
+	pub fn new(id: u32, details: KittyDetails<AccountId, Balance>) -> Self {
+		SnapshotKitty {
+			id,
+			dna: details.dna.to_vec().into(),
+			owner: details.owner,
+			price: details.price,
+		}
+	}
+}
+
+impl KittiesSnapshot {
+	/// Read a snapshot from a JSON file.
+	pub fn from_json_file(path: &Path) -> Result<Self, String> {
+		let file = File::open(path)
+			.map_err(|e| format!("Error opening kitties snapshot {}: {}", path.display(), e))?;
+		serde_json::from_reader(file)
+			.map_err(|e| format!("Error parsing kitties snapshot {}: {}", path.display(), e))
+	}
+
+	/// Write the snapshot to a JSON file, replacing it if it exists.
+	pub fn write_json_file(&self, path: &Path) -> Result<(), String> {
+		let file = File::create(path)
+			.map_err(|e| format!("Error creating kitties snapshot {}: {}", path.display(), e))?;
+		serde_json::to_writer_pretty(file, self)
+			.map_err(|e| format!("Error writing kitties snapshot {}: {}", path.display(), e))
+	}
+
+	/// The kitties as `KittiesConfig` entries. Genesis gives kitties indexes in order, so the
+	/// snapshot must hold every kitty from index 0 for them to keep their indexes.
+	pub fn into_genesis(self) -> Result<Vec<(AccountId, [u8; 16], Option<Balance>)>, String> {
+		self.kitties.into_iter().enumerate().map(|(index, kitty)| {
+			if kitty.id as usize != index {
+				return Err(format!("Kitty {} found at position {} of the snapshot, kitties must be numbered from 0 without gaps", kitty.id, index));
+			}
+			if kitty.dna.0.len() != 16 {
+				return Err(format!("Kitty {} has {} bytes of DNA, expected 16", kitty.id, kitty.dna.0.len()));
+			}
+			let mut dna = [0u8; 16];
+			dna.copy_from_slice(&kitty.dna.0);
+			Ok((kitty.owner, dna, kitty.price))
+		}).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tempdir::TempDir;
+
+	fn kitty(id: u32, dna: &[u8], price: Option<Balance>) -> SnapshotKitty {
+		SnapshotKitty {
+			id,
+			dna: dna.to_vec().into(),
+			owner: AccountId::from_raw([id as u8; 32]),
+			price,
+		}
+	}
+
+	fn snapshot(kitties: Vec<SnapshotKitty>) -> KittiesSnapshot {
+		KittiesSnapshot { block_hash: H256::repeat_byte(1), block_number: 10, kitties }
+	}
+
+	#[test]
+	fn contiguous_kitties_become_genesis_entries() {
+		let genesis = snapshot(vec![kitty(0, &[1; 16], None), kitty(1, &[2; 16], Some(500))]).into_genesis();
+		assert_eq!(genesis, Ok(vec![
+			(AccountId::from_raw([0; 32]), [1; 16], None),
+			(AccountId::from_raw([1; 32]), [2; 16], Some(500)),
+		]));
+		assert_eq!(snapshot(Vec::new()).into_genesis(), Ok(Vec::new()));
+	}
+
+	#[test]
+	fn kitty_ids_must_be_contiguous_from_zero() {
+		let error = snapshot(vec![kitty(1, &[1; 16], None)]).into_genesis().unwrap_err();
+		assert!(error.starts_with("Kitty 1 found at position 0"), "{}", error);
+
+		let gap = snapshot(vec![kitty(0, &[1; 16], None), kitty(2, &[2; 16], None)]);
+		assert!(gap.into_genesis().unwrap_err().starts_with("Kitty 2 found at position 1"));
+
+		let swapped = snapshot(vec![kitty(1, &[1; 16], None), kitty(0, &[2; 16], None)]);
+		assert!(swapped.into_genesis().is_err());
+	}
+
+	#[test]
+	fn dna_must_be_16_bytes() {
+		let error = snapshot(vec![kitty(0, &[1; 15], None)]).into_genesis().unwrap_err();
+		assert_eq!(error, "Kitty 0 has 15 bytes of DNA, expected 16");
+	}
+
+	#[test]
+	fn snapshots_round_trip_through_json() {
+		let dir = TempDir::new("kitties-snapshot").unwrap();
+		let path = dir.path().join("kitties.json");
+		snapshot(vec![kitty(0, &[1; 16], Some(500))]).write_json_file(&path).unwrap();
+
+		let read = KittiesSnapshot::from_json_file(&path).unwrap();
+		assert_eq!((read.block_hash, read.block_number), (H256::repeat_byte(1), 10));
+		assert_eq!(read.into_genesis(), Ok(vec![(AccountId::from_raw([0; 32]), [1; 16], Some(500))]));
+	}
+}